@@ -0,0 +1,4 @@
+CREATE TABLE subscription_tokens(
+  subscription_token TEXT NOT NULL PRIMARY KEY,
+  subscriber_id INTEGER NOT NULL REFERENCES subscriptions (id)
+);
@@ -0,0 +1,7 @@
+CREATE TABLE newsletter_issues(
+  id INTEGER NOT NULL PRIMARY KEY,
+  title TEXT NOT NULL,
+  text_content TEXT NOT NULL,
+  html_content TEXT NOT NULL,
+  published_at timestamptz NOT NULL
+);
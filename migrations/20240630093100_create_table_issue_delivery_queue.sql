@@ -0,0 +1,5 @@
+CREATE TABLE issue_delivery_queue(
+  newsletter_issue_id INTEGER NOT NULL REFERENCES newsletter_issues (id),
+  subscriber_email TEXT NOT NULL,
+  PRIMARY KEY (newsletter_issue_id, subscriber_email)
+);
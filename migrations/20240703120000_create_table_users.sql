@@ -0,0 +1,5 @@
+CREATE TABLE users(
+  id INTEGER NOT NULL PRIMARY KEY,
+  username TEXT NOT NULL UNIQUE,
+  password_hash TEXT NOT NULL
+);
@@ -0,0 +1,6 @@
+CREATE TABLE unsubscribe_tokens(
+  unsubscribe_token TEXT NOT NULL PRIMARY KEY,
+  subscriber_id INTEGER NOT NULL UNIQUE REFERENCES subscriptions (id)
+);
+INSERT INTO unsubscribe_tokens (unsubscribe_token, subscriber_id)
+SELECT lower(hex(randomblob(16))), id FROM subscriptions;
@@ -0,0 +1,4 @@
+UPDATE newsletter_issues SET status = 'sending'
+WHERE status = 'published'
+AND id IN (SELECT newsletter_issue_id FROM issue_delivery_queue);
+UPDATE newsletter_issues SET status = 'sent' WHERE status = 'published';
@@ -0,0 +1,6 @@
+CREATE TABLE subscriptions(
+  id BIGSERIAL NOT NULL PRIMARY KEY,
+  email TEXT NOT NULL UNIQUE,
+  name TEXT NOT NULL,
+  subscribed_at timestamptz NOT NULL
+);
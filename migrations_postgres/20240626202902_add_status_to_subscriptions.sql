@@ -0,0 +1 @@
+ALTER TABLE subscriptions ADD COLUMN status TEXT NULL DEFAULT 'confirmed';
@@ -0,0 +1,5 @@
+CREATE TABLE users(
+  id BIGSERIAL NOT NULL PRIMARY KEY,
+  username TEXT NOT NULL UNIQUE,
+  password_hash TEXT NOT NULL
+);
@@ -0,0 +1,5 @@
+CREATE TABLE sessions(
+  session_key TEXT NOT NULL PRIMARY KEY,
+  session_state TEXT NOT NULL,
+  expires_at timestamptz NOT NULL
+);
@@ -0,0 +1,9 @@
+CREATE TABLE idempotency(
+  user_id BIGINT NOT NULL REFERENCES users (id),
+  idempotency_key TEXT NOT NULL,
+  response_status_code INTEGER NULL,
+  response_headers TEXT NULL,
+  response_body BYTEA NULL,
+  created_at timestamptz NOT NULL,
+  PRIMARY KEY (user_id, idempotency_key)
+);
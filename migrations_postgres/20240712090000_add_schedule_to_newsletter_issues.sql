@@ -0,0 +1,2 @@
+ALTER TABLE newsletter_issues ADD COLUMN status TEXT NOT NULL DEFAULT 'published';
+ALTER TABLE newsletter_issues ADD COLUMN send_at timestamptz;
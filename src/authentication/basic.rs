@@ -0,0 +1,69 @@
+use super::Credentials;
+use actix_web::http::header::HeaderMap;
+use base64::Engine;
+use secrecy::Secret;
+
+pub fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, String> {
+    let header_value = headers
+        .get("Authorization")
+        .ok_or("The 'Authorization' header was missing")?
+        .to_str()
+        .map_err(|_| "The 'Authorization' header was not a valid UTF8 string.")?;
+    let base64encoded_segment = header_value
+        .strip_prefix("Basic ")
+        .ok_or("The authorization scheme was not 'Basic'.")?;
+    let decoded_bytes = base64::engine::general_purpose::STANDARD
+        .decode(base64encoded_segment)
+        .map_err(|_| "Failed to base64-decode 'Basic' credentials.")?;
+    let decoded_credentials = String::from_utf8(decoded_bytes)
+        .map_err(|_| "The decoded credential string is not valid UTF8.")?;
+
+    let (username, password) = decoded_credentials
+        .split_once(':')
+        .ok_or("A password must be provided in 'Basic' auth.")?;
+    Ok(Credentials {
+        username: username.to_string(),
+        password: Secret::new(password.to_string()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::header::{HeaderValue, AUTHORIZATION};
+    use claim::{assert_err, assert_ok};
+    use secrecy::ExposeSecret;
+
+    fn headers(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn valid_basic_credentials_are_decoded() {
+        // "ursula:le:guin"
+        let credentials =
+            assert_ok!(basic_authentication(&headers("Basic dXJzdWxhOmxlOmd1aW4=")));
+        assert_eq!(credentials.username, "ursula");
+        assert_eq!(credentials.password.expose_secret(), "le:guin");
+    }
+
+    #[test]
+    fn a_missing_header_is_rejected() {
+        assert_err!(basic_authentication(&HeaderMap::new()));
+    }
+
+    #[test]
+    fn a_non_basic_scheme_is_rejected() {
+        assert_err!(basic_authentication(&headers(
+            "Bearer dXJzdWxhOmxlZ3Vpbg=="
+        )));
+    }
+
+    #[test]
+    fn credentials_without_a_password_are_rejected() {
+        // "ursula"
+        assert_err!(basic_authentication(&headers("Basic dXJzdWxh")));
+    }
+}
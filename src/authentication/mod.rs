@@ -0,0 +1,10 @@
+mod basic;
+mod middleware;
+mod password;
+
+pub use basic::basic_authentication;
+pub use middleware::{reject_anonymous_users, UserId};
+pub use password::{
+    compute_password_hash, create_user_if_missing, validate_credentials, AuthError,
+    Credentials,
+};
@@ -0,0 +1,183 @@
+use crate::database::DbPool;
+use argon2::password_hash::SaltString;
+use argon2::{
+    Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version,
+};
+use secrecy::{ExposeSecret, Secret};
+
+#[derive(Debug)]
+pub struct Credentials {
+    pub username: String,
+    pub password: Secret<String>,
+}
+
+#[tracing::instrument(name = "Validate credentials", skip(credentials, pool))]
+pub async fn validate_credentials(
+    credentials: Credentials,
+    pool: &DbPool,
+) -> Result<i64, AuthError> {
+    // Unknown usernames are checked against a dummy hash, so the response time
+    // does not reveal which usernames exist.
+    let mut user_id = None;
+    let mut expected_password_hash = Secret::new(
+        "$argon2id$v=19$m=15000,t=2,p=1$\
+        gZiV/M1gPc22ElAH/Jh1Hw$\
+        CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno"
+            .to_string(),
+    );
+    if let Some((stored_user_id, stored_password_hash)) =
+        get_stored_credentials(&credentials.username, pool).await?
+    {
+        user_id = Some(stored_user_id);
+        expected_password_hash = stored_password_hash;
+    }
+    spawn_blocking_with_tracing(move || {
+        verify_password_hash(expected_password_hash, credentials.password)
+    })
+    .await
+    .map_err(|e| AuthError::UnexpectedError(e.to_string()))??;
+    user_id.ok_or(AuthError::InvalidCredentials)
+}
+
+#[tracing::instrument(name = "Get stored credentials", skip(username, pool))]
+async fn get_stored_credentials(
+    username: &str,
+    pool: &DbPool,
+) -> Result<Option<(i64, Secret<String>)>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+            SELECT id, password_hash FROM users
+            WHERE username = $1
+        "#,
+        username,
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?
+    .map(|row| (row.id, Secret::new(row.password_hash)));
+    Ok(row)
+}
+
+#[tracing::instrument(
+    name = "Verify password hash",
+    skip(expected_password_hash, password_candidate)
+)]
+fn verify_password_hash(
+    expected_password_hash: Secret<String>,
+    password_candidate: Secret<String>,
+) -> Result<(), AuthError> {
+    let expected_password_hash =
+        PasswordHash::new(expected_password_hash.expose_secret())
+            .map_err(|e| AuthError::UnexpectedError(e.to_string()))?;
+    Argon2::default()
+        .verify_password(
+            password_candidate.expose_secret().as_bytes(),
+            &expected_password_hash,
+        )
+        .map_err(|_| AuthError::InvalidCredentials)
+}
+
+pub fn compute_password_hash(
+    password: Secret<String>,
+) -> Result<Secret<String>, AuthError> {
+    let salt = SaltString::generate(&mut rand::thread_rng());
+    let password_hash = Argon2::new(
+        Algorithm::Argon2id,
+        Version::V0x13,
+        Params::new(15000, 2, 1, None).unwrap(),
+    )
+    .hash_password(password.expose_secret().as_bytes(), &salt)
+    .map_err(|e| AuthError::UnexpectedError(e.to_string()))?
+    .to_string();
+    Ok(Secret::new(password_hash))
+}
+
+#[tracing::instrument(
+    name = "Seed a user",
+    skip(credentials, pool),
+    fields(username = %credentials.username)
+)]
+pub async fn create_user_if_missing(
+    credentials: Credentials,
+    pool: &DbPool,
+) -> Result<(), AuthError> {
+    let username = credentials.username;
+    let password_hash =
+        spawn_blocking_with_tracing(move || compute_password_hash(credentials.password))
+            .await
+            .map_err(|e| AuthError::UnexpectedError(e.to_string()))??;
+    let password_hash = password_hash.expose_secret();
+    sqlx::query!(
+        r#"
+            INSERT INTO users (username, password_hash)
+            VALUES ($1, $2)
+            ON CONFLICT DO NOTHING
+        "#,
+        username,
+        password_hash,
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(())
+}
+
+fn spawn_blocking_with_tracing<F, R>(f: F) -> tokio::task::JoinHandle<R>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let current_span = tracing::Span::current();
+    tokio::task::spawn_blocking(move || current_span.in_scope(f))
+}
+
+#[derive(Debug)]
+pub enum AuthError {
+    InvalidCredentials,
+    DatabaseError(sqlx::Error),
+    UnexpectedError(String),
+}
+
+impl std::error::Error for AuthError {}
+
+impl From<sqlx::Error> for AuthError {
+    fn from(e: sqlx::Error) -> Self {
+        Self::DatabaseError(e)
+    }
+}
+
+impl std::fmt::Display for AuthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AuthError::InvalidCredentials => write!(f, "Invalid credentials."),
+            AuthError::DatabaseError(e) => write!(f, "Failed to query users: {}", e),
+            AuthError::UnexpectedError(e) => write!(f, "Failed to authenticate: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use claim::{assert_err, assert_ok};
+
+    #[test]
+    fn a_password_matches_its_own_hash() {
+        let password = Secret::new("everythinghastostartsomewhere".to_string());
+        let hash = compute_password_hash(password.clone()).unwrap();
+        assert!(hash.expose_secret().starts_with("$argon2id$"));
+        assert_ok!(verify_password_hash(hash, password));
+    }
+
+    #[test]
+    fn a_different_password_is_rejected() {
+        let hash = compute_password_hash(Secret::new("right".to_string())).unwrap();
+        assert_err!(verify_password_hash(hash, Secret::new("wrong".to_string())));
+    }
+}
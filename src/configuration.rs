@@ -1,40 +1,279 @@
+use crate::authentication::Credentials;
+use crate::database::DbConnectOptions;
 use crate::domain::SubscriberEmail;
+use crate::email_client::{
+    EmailBackend, EmailClient, EmailProvider, EmailTransportKind, HttpApiTransport,
+    RateLimiter, SmtpAuthentication, SmtpCredentials, SmtpTls, SmtpTransport,
+};
+use crate::issue_delivery_worker::RetryPolicy;
+use crate::session_store::SessionStoreKind;
 use config::{Config, ConfigError, File, FileFormat};
+use secrecy::Secret;
 use std::convert::{TryFrom, TryInto};
+use std::str::FromStr;
+use std::time::Duration;
 
 #[derive(serde::Deserialize, Debug)]
 pub struct Settings {
     pub database: DatabaseSettings,
     pub application: ApplicationSettings,
     pub email_client: EmailClientSettings,
+    pub admin: Option<AdminSettings>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct AdminSettings {
+    pub username: String,
+    pub password: Secret<String>,
+}
+
+impl AdminSettings {
+    pub fn credentials(&self) -> Credentials {
+        Credentials {
+            username: self.username.clone(),
+            password: self.password.clone(),
+        }
+    }
 }
 
 #[derive(serde::Deserialize, Debug)]
 pub struct EmailClientSettings {
+    pub transport: EmailTransportKind,
+    #[serde(default)]
+    pub provider: EmailProvider,
     pub base_url: String,
     pub sender_email: String,
+    pub authorization_token: Option<Secret<String>>,
+    pub authorization_token_file: Option<String>,
+    pub timeout_milliseconds: u64,
+    pub smtp: Option<SmtpSettings>,
+    pub max_retries: u32,
+    pub retry_base_delay_milliseconds: u64,
+    pub retry_max_delay_milliseconds: u64,
+    pub max_messages_per_second: u32,
+    pub max_burst_size: u32,
+    pub max_concurrent_requests: usize,
 }
 
 impl EmailClientSettings {
     pub fn sender(&self) -> Result<SubscriberEmail, String> {
         SubscriberEmail::parse(self.sender_email.clone())
     }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_milliseconds)
+    }
+
+    // An inline token (e.g. from `APP_EMAIL_CLIENT__AUTHORIZATION_TOKEN`) takes
+    // precedence over a mounted secret file.
+    pub fn authorization_token(&self) -> Result<Option<Secret<String>>, String> {
+        if let Some(token) = &self.authorization_token {
+            return Ok(Some(token.clone()));
+        }
+        let Some(path) = &self.authorization_token_file else {
+            return Ok(None);
+        };
+        let token = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read the authorization token file: {}", e))?;
+        Ok(Some(Secret::new(token.trim().to_owned())))
+    }
+
+    pub fn client(&self) -> Result<EmailClient, String> {
+        let sender = self.sender()?;
+        // Any of these at zero would leave every send waiting forever.
+        for (name, value) in [
+            (
+                "max_messages_per_second",
+                self.max_messages_per_second as usize,
+            ),
+            ("max_burst_size", self.max_burst_size as usize),
+            ("max_concurrent_requests", self.max_concurrent_requests),
+        ] {
+            if value == 0 {
+                return Err(format!("`email_client.{}` must be at least 1.", name));
+            }
+        }
+        let transport = match self.transport {
+            EmailTransportKind::Http => EmailBackend::Http(HttpApiTransport::new(
+                self.base_url.clone(),
+                self.provider,
+                self.authorization_token()?,
+                self.timeout(),
+            )),
+            EmailTransportKind::Smtp => {
+                let smtp = self.smtp.as_ref().ok_or_else(|| {
+                    "The smtp transport requires `email_client.smtp` settings."
+                        .to_string()
+                })?;
+                EmailBackend::Smtp(smtp.transport(self.timeout())?)
+            }
+        };
+        Ok(
+            EmailClient::new(transport, sender).with_rate_limiter(RateLimiter::new(
+                self.max_messages_per_second,
+                self.max_burst_size,
+                self.max_concurrent_requests,
+            )),
+        )
+    }
+
+    pub fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            max_retries: self.max_retries,
+            base_delay: Duration::from_millis(self.retry_base_delay_milliseconds),
+            max_delay: Duration::from_millis(self.retry_max_delay_milliseconds),
+        }
+    }
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct SmtpSettings {
+    pub host: String,
+    // Defaults to the usual port for the TLS mode.
+    pub port: Option<u16>,
+    pub tls: SmtpTls,
+    pub username: Option<String>,
+    pub password: Option<Secret<String>>,
+    #[serde(default)]
+    pub authentication: SmtpAuthentication,
+}
+
+impl SmtpSettings {
+    pub fn transport(&self, timeout: Duration) -> Result<SmtpTransport, String> {
+        let credentials = match (&self.username, &self.password) {
+            (Some(username), Some(password)) => Some(SmtpCredentials {
+                username: username.clone(),
+                password: password.clone(),
+                authentication: self.authentication,
+            }),
+            (None, None) => None,
+            _ => return Err("SMTP username and password must be set together.".into()),
+        };
+        SmtpTransport::new(&self.host, self.port, self.tls, credentials, timeout)
+            .map_err(|e| e.to_string())
+    }
 }
 
 #[derive(serde::Deserialize, Debug)]
 pub struct ApplicationSettings {
     pub port: u16,
     pub host: String,
+    pub base_url: String,
+    pub hmac_secret: Secret<String>,
+    pub session_store: SessionStoreKind,
+    pub templates_directory: String,
 }
 
 #[derive(serde::Deserialize, Debug)]
 pub struct DatabaseSettings {
-    pub filename: String,
+    // `sqlite://...` or `postgres://...`, matching the backend the binary
+    // was built for.
+    pub url: String,
+    pub max_connections: u32,
+    pub min_connections: u32,
+    pub idle_timeout_seconds: u64,
+    pub acquire_timeout_milliseconds: u64,
+    // Applies the migrations embedded in the binary before serving requests.
+    pub migrate_on_startup: bool,
+    // Ignored when running against Postgres.
+    #[serde(default)]
+    pub sqlite: SqliteSettings,
 }
 
 impl DatabaseSettings {
     pub fn connection_string(&self) -> String {
-        self.filename.to_string()
+        self.url.to_string()
+    }
+
+    pub fn idle_timeout(&self) -> Duration {
+        Duration::from_secs(self.idle_timeout_seconds)
+    }
+
+    pub fn acquire_timeout(&self) -> Duration {
+        Duration::from_millis(self.acquire_timeout_milliseconds)
+    }
+
+    #[cfg(feature = "sqlite")]
+    pub fn connect_options(&self) -> Result<DbConnectOptions, sqlx::Error> {
+        let options = DbConnectOptions::from_str(&self.url)?
+            .journal_mode(self.sqlite.journal_mode.into())
+            .synchronous(self.sqlite.synchronous.into())
+            .busy_timeout(Duration::from_millis(self.sqlite.busy_timeout_milliseconds))
+            .foreign_keys(self.sqlite.foreign_keys)
+            .create_if_missing(self.sqlite.create_if_missing);
+        Ok(options)
+    }
+
+    #[cfg(feature = "postgres")]
+    pub fn connect_options(&self) -> Result<DbConnectOptions, sqlx::Error> {
+        DbConnectOptions::from_str(&self.url)
+    }
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct SqliteSettings {
+    pub journal_mode: SqliteJournalMode,
+    pub synchronous: SqliteSynchronous,
+    pub busy_timeout_milliseconds: u64,
+    pub foreign_keys: bool,
+    pub create_if_missing: bool,
+}
+
+impl Default for SqliteSettings {
+    fn default() -> Self {
+        Self {
+            journal_mode: SqliteJournalMode::Wal,
+            synchronous: SqliteSynchronous::Normal,
+            busy_timeout_milliseconds: 5000,
+            foreign_keys: true,
+            create_if_missing: true,
+        }
+    }
+}
+
+#[derive(serde::Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum SqliteJournalMode {
+    Delete,
+    Truncate,
+    Persist,
+    Memory,
+    Wal,
+    Off,
+}
+
+#[cfg(feature = "sqlite")]
+impl From<SqliteJournalMode> for sqlx::sqlite::SqliteJournalMode {
+    fn from(mode: SqliteJournalMode) -> Self {
+        match mode {
+            SqliteJournalMode::Delete => Self::Delete,
+            SqliteJournalMode::Truncate => Self::Truncate,
+            SqliteJournalMode::Persist => Self::Persist,
+            SqliteJournalMode::Memory => Self::Memory,
+            SqliteJournalMode::Wal => Self::Wal,
+            SqliteJournalMode::Off => Self::Off,
+        }
+    }
+}
+
+#[derive(serde::Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum SqliteSynchronous {
+    Off,
+    Normal,
+    Full,
+    Extra,
+}
+
+#[cfg(feature = "sqlite")]
+impl From<SqliteSynchronous> for sqlx::sqlite::SqliteSynchronous {
+    fn from(synchronous: SqliteSynchronous) -> Self {
+        match synchronous {
+            SqliteSynchronous::Off => Self::Off,
+            SqliteSynchronous::Normal => Self::Normal,
+            SqliteSynchronous::Full => Self::Full,
+            SqliteSynchronous::Extra => Self::Extra,
+        }
     }
 }
 
@@ -47,6 +286,11 @@ pub fn get_configuration() -> Result<Settings, ConfigError> {
     Config::builder()
         .add_source(File::new("configuration/base.yaml", FileFormat::Yaml))
         .add_source(File::new(&env_config, FileFormat::Yaml))
+        .add_source(
+            config::Environment::with_prefix("APP")
+                .prefix_separator("_")
+                .separator("__"),
+        )
         .build()
         .unwrap()
         .try_deserialize()
@@ -82,3 +326,179 @@ impl TryFrom<String> for Environment {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::EmailClientSettings;
+    use config::{Config, File, FileFormat};
+    use secrecy::ExposeSecret;
+
+    fn email_client_settings(extra: &str) -> EmailClientSettings {
+        let yaml = format!(
+            "transport: http\n\
+            base_url: localhost\n\
+            sender_email: test@ya.ru\n\
+            timeout_milliseconds: 10000\n\
+            max_retries: 5\n\
+            retry_base_delay_milliseconds: 30000\n\
+            retry_max_delay_milliseconds: 3600000\n\
+            max_messages_per_second: 10\n\
+            max_burst_size: 10\n\
+            max_concurrent_requests: 4\n\
+            {}",
+            extra
+        );
+        Config::builder()
+            .add_source(File::from_str(&yaml, FileFormat::Yaml))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap()
+    }
+
+    #[test]
+    fn authorization_token_is_read_from_a_secret_file() {
+        let path = std::env::temp_dir().join(format!("token_{}", std::process::id()));
+        std::fs::write(&path, "file-token\n").unwrap();
+        let settings = email_client_settings(&format!(
+            "authorization_token_file: {}",
+            path.display()
+        ));
+
+        let token = settings.authorization_token().unwrap().unwrap();
+
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(token.expose_secret(), "file-token");
+    }
+
+    #[test]
+    fn an_inline_authorization_token_takes_precedence_over_the_file() {
+        let settings = email_client_settings(
+            "authorization_token: inline-token\n\
+            authorization_token_file: /does/not/exist",
+        );
+
+        let token = settings.authorization_token().unwrap().unwrap();
+
+        assert_eq!(token.expose_secret(), "inline-token");
+    }
+
+    #[test]
+    fn zero_concurrent_requests_are_rejected() {
+        let mut settings = email_client_settings("");
+        settings.max_concurrent_requests = 0;
+
+        let error = settings.client().err().unwrap();
+        assert!(error.contains("max_concurrent_requests"));
+    }
+
+    #[test]
+    fn zero_messages_per_second_is_rejected() {
+        let mut settings = email_client_settings("");
+        settings.max_messages_per_second = 0;
+
+        let error = settings.client().err().unwrap();
+        assert!(error.contains("max_messages_per_second"));
+    }
+
+    #[test]
+    fn zero_burst_size_is_rejected() {
+        let mut settings = email_client_settings("");
+        settings.max_burst_size = 0;
+
+        let error = settings.client().err().unwrap();
+        assert!(error.contains("max_burst_size"));
+    }
+
+    #[test]
+    fn authorization_token_is_redacted_from_debug_output() {
+        let settings = email_client_settings("authorization_token: inline-token");
+
+        assert!(!format!("{:?}", settings).contains("inline-token"));
+    }
+
+    #[cfg(feature = "sqlite")]
+    fn database_settings(path: &std::path::Path, extra: &str) -> super::DatabaseSettings {
+        let yaml = format!(
+            "url: sqlite://{}\n\
+            max_connections: 2\n\
+            min_connections: 0\n\
+            idle_timeout_seconds: 60\n\
+            acquire_timeout_milliseconds: 1000\n\
+            migrate_on_startup: false\n\
+            {}",
+            path.display(),
+            extra
+        );
+        Config::builder()
+            .add_source(File::from_str(&yaml, FileFormat::Yaml))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap()
+    }
+
+    // Returns `journal_mode`, `synchronous`, `busy_timeout` and `foreign_keys`
+    // as seen by a fresh connection.
+    #[cfg(feature = "sqlite")]
+    async fn pragmas(name: &str, extra: &str) -> (String, i64, i64, i64) {
+        use sqlx::{Connection, SqliteConnection};
+
+        let path =
+            std::env::temp_dir().join(format!("{}_{}.db", name, std::process::id()));
+        let settings = database_settings(&path, extra);
+        let mut connection =
+            SqliteConnection::connect_with(&settings.connect_options().unwrap())
+                .await
+                .unwrap();
+        let journal_mode = sqlx::query_scalar("PRAGMA journal_mode")
+            .fetch_one(&mut connection)
+            .await
+            .unwrap();
+        let mut values = Vec::new();
+        for pragma in ["synchronous", "busy_timeout", "foreign_keys"] {
+            let value: i64 = sqlx::query_scalar(&format!("PRAGMA {}", pragma))
+                .fetch_one(&mut connection)
+                .await
+                .unwrap();
+            values.push(value);
+        }
+        connection.close().await.unwrap();
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+        (journal_mode, values[0], values[1], values[2])
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn sqlite_connections_default_to_wal_with_foreign_keys() {
+        let (journal_mode, synchronous, busy_timeout, foreign_keys) =
+            pragmas("default_pragmas", "").await;
+
+        assert_eq!(journal_mode, "wal");
+        assert_eq!(synchronous, 1);
+        assert_eq!(busy_timeout, 5000);
+        assert_eq!(foreign_keys, 1);
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn sqlite_pragmas_follow_the_configuration() {
+        let (journal_mode, synchronous, busy_timeout, foreign_keys) = pragmas(
+            "configured_pragmas",
+            "sqlite:\n  \
+            journal_mode: delete\n  \
+            synchronous: full\n  \
+            busy_timeout_milliseconds: 250\n  \
+            foreign_keys: false\n  \
+            create_if_missing: true",
+        )
+        .await;
+
+        assert_eq!(journal_mode, "delete");
+        assert_eq!(synchronous, 2);
+        assert_eq!(busy_timeout, 250);
+        assert_eq!(foreign_keys, 0);
+    }
+}
@@ -0,0 +1,57 @@
+use sqlx::migrate::{MigrateError, Migrator};
+
+// The backend is picked at compile time so that every `query!` keeps being
+// checked against the schema it will actually run on.
+#[cfg(all(feature = "sqlite", feature = "postgres"))]
+compile_error!(
+    "The `sqlite` and `postgres` features are mutually exclusive. \
+    Build with `--no-default-features --features postgres` to use Postgres."
+);
+
+#[cfg(not(any(feature = "sqlite", feature = "postgres")))]
+compile_error!("Either the `sqlite` or the `postgres` feature must be enabled.");
+
+#[cfg(feature = "sqlite")]
+pub type Db = sqlx::Sqlite;
+#[cfg(feature = "postgres")]
+pub type Db = sqlx::Postgres;
+
+pub type DbPool = sqlx::Pool<Db>;
+pub type DbPoolOptions = sqlx::pool::PoolOptions<Db>;
+#[cfg(feature = "sqlite")]
+pub type DbConnectOptions = sqlx::sqlite::SqliteConnectOptions;
+#[cfg(feature = "postgres")]
+pub type DbConnectOptions = sqlx::postgres::PgConnectOptions;
+
+// Both directories hold the same migrations, written for each dialect.
+#[cfg(feature = "sqlite")]
+pub static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+#[cfg(feature = "postgres")]
+pub static MIGRATOR: Migrator = sqlx::migrate!("./migrations_postgres");
+
+// Migrations the database has applied but this binary does not ship make
+// the run fail instead of being ignored: the schema is newer than the code.
+#[cfg(feature = "postgres")]
+pub async fn run_migrations(pool: &DbPool) -> Result<(), MigrateError> {
+    // sqlx holds an advisory lock for the whole run.
+    MIGRATOR.run(pool).await
+}
+
+#[cfg(feature = "sqlite")]
+pub async fn run_migrations(pool: &DbPool) -> Result<(), MigrateError> {
+    // SQLite has no advisory locks, so the run happens inside a single
+    // transaction that takes the write lock before anything else. A second
+    // instance waits for it and then finds nothing left to apply.
+    sqlx::query("CREATE TABLE IF NOT EXISTS _migrations_lock (id INTEGER)")
+        .execute(pool)
+        .await?;
+    let mut transaction = pool.begin().await?;
+    // Starting with a write makes SQLite wait for the lock; upgrading a read
+    // would fail straight away if another connection wrote in between.
+    sqlx::query("DELETE FROM _migrations_lock")
+        .execute(transaction.as_mut())
+        .await?;
+    MIGRATOR.run(&mut transaction).await?;
+    transaction.commit().await?;
+    Ok(())
+}
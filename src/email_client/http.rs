@@ -0,0 +1,348 @@
+use super::{mailgun, postmark, sendgrid, ses};
+use super::{BatchRecipient, EmailBatch, EmailError, EmailMessage, EmailTransport};
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::{Client, RequestBuilder, StatusCode};
+use secrecy::Secret;
+use std::time::Duration;
+
+#[derive(serde::Deserialize, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum EmailProvider {
+    #[default]
+    Postmark,
+    SendGrid,
+    Mailgun,
+    Ses,
+}
+
+#[derive(Clone)]
+pub struct HttpApiTransport {
+    http_client: Client,
+    base_url: String,
+    provider: EmailProvider,
+    authorization_token: Option<Secret<String>>,
+}
+
+impl HttpApiTransport {
+    pub fn new(
+        base_url: String,
+        provider: EmailProvider,
+        authorization_token: Option<Secret<String>>,
+        timeout: Duration,
+    ) -> Self {
+        let http_client = Client::builder().timeout(timeout).build().unwrap();
+        Self {
+            http_client,
+            base_url,
+            provider,
+            authorization_token,
+        }
+    }
+}
+
+impl EmailProvider {
+    fn max_batch_size(&self) -> usize {
+        match self {
+            EmailProvider::Postmark => 500,
+            EmailProvider::SendGrid => 1000,
+            EmailProvider::Mailgun => 1000,
+            EmailProvider::Ses => 50,
+        }
+    }
+}
+
+impl HttpApiTransport {
+    // Returns the body of a successful response.
+    async fn execute(&self, request: RequestBuilder) -> Result<String, EmailError> {
+        let response = request.send().await?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response.text().await.unwrap_or_default());
+        }
+        let retry_after = retry_after(response.headers());
+        let body = response.text().await.unwrap_or_default();
+        let message = match self.provider {
+            EmailProvider::Postmark => postmark::error_message(&body),
+            EmailProvider::SendGrid => sendgrid::error_message(&body),
+            EmailProvider::Mailgun => mailgun::error_message(&body),
+            EmailProvider::Ses => ses::error_message(&body),
+        }
+        .unwrap_or_else(|| body.clone());
+        Err(match status {
+            StatusCode::TOO_MANY_REQUESTS => {
+                EmailError::RateLimited { retry_after, body }
+            }
+            StatusCode::REQUEST_TIMEOUT => EmailError::Timeout(message),
+            status if status.is_client_error() => EmailError::Rejected {
+                status: status.as_u16(),
+                message,
+                body,
+            },
+            status => EmailError::ProviderError {
+                status: status.as_u16(),
+                message,
+                body,
+            },
+        })
+    }
+
+    async fn send_chunk(
+        &self,
+        batch: &EmailBatch<'_>,
+        recipients: &[BatchRecipient],
+    ) -> Vec<Result<(), EmailError>> {
+        let (client, base_url) = (&self.http_client, self.base_url.as_str());
+        let token = self.authorization_token.as_ref();
+        let request = match self.provider {
+            EmailProvider::Postmark => {
+                postmark::build_batch_request(client, base_url, batch, recipients, token)
+            }
+            EmailProvider::SendGrid => {
+                sendgrid::build_batch_request(client, base_url, batch, recipients, token)
+            }
+            EmailProvider::Mailgun => {
+                mailgun::build_batch_request(client, base_url, batch, recipients, token)
+            }
+            EmailProvider::Ses => {
+                ses::build_batch_request(client, base_url, batch, recipients, token)
+            }
+        };
+        let body = match self.execute(request).await {
+            Ok(body) => body,
+            Err(e) => return recipients.iter().map(|_| Err(e.clone())).collect(),
+        };
+        let results = match self.provider {
+            EmailProvider::Postmark => postmark::batch_results(&body, recipients.len()),
+            EmailProvider::Ses => ses::batch_results(&body, recipients.len()),
+            // Both accept or reject the whole request.
+            EmailProvider::SendGrid | EmailProvider::Mailgun => {
+                recipients.iter().map(|_| Ok(())).collect()
+            }
+        };
+        // Callers match results to recipients by position, so a response
+        // with a different number of entries cannot be trusted for any of them.
+        if results.len() != recipients.len() {
+            let error = EmailError::MalformedResponse {
+                message: format!(
+                    "Expected {} results in the batch response, got {}",
+                    recipients.len(),
+                    results.len()
+                ),
+                body,
+            };
+            return recipients.iter().map(|_| Err(error.clone())).collect();
+        }
+        results
+    }
+}
+
+impl EmailTransport for HttpApiTransport {
+    async fn send(&self, message: &EmailMessage<'_>) -> Result<(), EmailError> {
+        let (client, base_url) = (&self.http_client, self.base_url.as_str());
+        let token = self.authorization_token.as_ref();
+        let request = match self.provider {
+            EmailProvider::Postmark => {
+                postmark::build_request(client, base_url, message, token)
+            }
+            EmailProvider::SendGrid => {
+                sendgrid::build_request(client, base_url, message, token)
+            }
+            EmailProvider::Mailgun => {
+                mailgun::build_request(client, base_url, message, token)
+            }
+            EmailProvider::Ses => ses::build_request(client, base_url, message, token),
+        };
+        self.execute(request).await.map(|_| ())
+    }
+
+    async fn send_batch(&self, batch: &EmailBatch<'_>) -> Vec<Result<(), EmailError>> {
+        let mut results = Vec::with_capacity(batch.recipients.len());
+        for recipients in batch.recipients.chunks(self.provider.max_batch_size()) {
+            results.extend(self.send_chunk(batch, recipients).await);
+        }
+        results
+    }
+}
+
+// `Retry-After` is either a number of seconds or an HTTP date.
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?;
+    if let Ok(seconds) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    (date.with_timezone(&chrono::Utc) - chrono::Utc::now())
+        .to_std()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::test_helpers::email_client;
+    use crate::email_client::{
+        EmailBackend, EmailClient, EmailError, EmailProvider, HttpApiTransport,
+    };
+    use claim::{assert_err, assert_ok};
+    use fake::faker::internet::en::SafeEmail;
+    use fake::faker::lorem::en::{Paragraph, Sentence};
+    use fake::Fake;
+    use wiremock::matchers::{body_partial_json, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn send_email_fires_a_request_to_base_url() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(EmailProvider::Postmark, mock_server.uri());
+        Mock::given(path("/email"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1) // one request
+            .mount(&mock_server)
+            .await;
+        let subscriber_email = SubscriberEmail::parse(SafeEmail().fake()).unwrap();
+        let subject: String = Sentence(1..2).fake();
+        let content: String = Paragraph(1..10).fake();
+        let outcome = email_client
+            .send_email(subscriber_email, &subject, &content, &content)
+            .await;
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_with_headers_includes_them_in_the_request_body() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(EmailProvider::Postmark, mock_server.uri());
+        Mock::given(path("/email"))
+            .and(method("POST"))
+            .and(body_partial_json(serde_json::json!({
+                "Headers": [{"Name": "List-Unsubscribe", "Value": "<https://x.y/z>"}]
+            })))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        let subscriber_email = SubscriberEmail::parse(SafeEmail().fake()).unwrap();
+        let subject: String = Sentence(1..2).fake();
+        let content: String = Paragraph(1..10).fake();
+        let outcome = email_client
+            .send_email_with_headers(
+                subscriber_email,
+                &subject,
+                &content,
+                &content,
+                &[("List-Unsubscribe", "<https://x.y/z>")],
+            )
+            .await;
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_times_out_if_the_server_takes_too_long() {
+        let mock_server = MockServer::start().await;
+        let sender = SubscriberEmail::parse(SafeEmail().fake()).unwrap();
+        let transport = HttpApiTransport::new(
+            mock_server.uri(),
+            EmailProvider::Postmark,
+            None,
+            std::time::Duration::from_millis(200),
+        );
+        let email_client = EmailClient::new(EmailBackend::Http(transport), sender);
+        Mock::given(path("/email"))
+            .respond_with(
+                ResponseTemplate::new(200).set_delay(std::time::Duration::from_secs(2)),
+            )
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        let subscriber_email = SubscriberEmail::parse(SafeEmail().fake()).unwrap();
+        let subject: String = Sentence(1..2).fake();
+        let content: String = Paragraph(1..10).fake();
+        let outcome = email_client
+            .send_email(subscriber_email, &subject, &content, &content)
+            .await;
+        let error = assert_err!(outcome);
+        assert!(matches!(error, EmailError::Timeout(_)));
+        assert!(error.is_retryable());
+    }
+
+    async fn send_email_answered_with(response: ResponseTemplate) -> EmailError {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(EmailProvider::Postmark, mock_server.uri());
+        Mock::given(path("/email"))
+            .respond_with(response)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        let subscriber_email = SubscriberEmail::parse(SafeEmail().fake()).unwrap();
+        let subject: String = Sentence(1..2).fake();
+        let content: String = Paragraph(1..10).fake();
+        email_client
+            .send_email(subscriber_email, &subject, &content, &content)
+            .await
+            .unwrap_err()
+    }
+
+    #[tokio::test]
+    async fn a_429_is_a_retryable_rate_limit_honouring_retry_after() {
+        let error = send_email_answered_with(
+            ResponseTemplate::new(429).insert_header("Retry-After", "120"),
+        )
+        .await;
+
+        assert!(matches!(error, EmailError::RateLimited { .. }));
+        assert!(error.is_retryable());
+        assert_eq!(
+            error.retry_after(),
+            Some(std::time::Duration::from_secs(120))
+        );
+    }
+
+    #[tokio::test]
+    async fn a_4xx_is_a_permanent_rejection_keeping_the_provider_body() {
+        let body = r#"{"ErrorCode": 300, "Message": "Invalid 'To' address."}"#;
+        let error =
+            send_email_answered_with(ResponseTemplate::new(422).set_body_string(body))
+                .await;
+
+        assert!(!error.is_retryable());
+        match error {
+            EmailError::Rejected {
+                status,
+                body: saved,
+                ..
+            } => {
+                assert_eq!(status, 422);
+                assert_eq!(saved, body);
+            }
+            other => panic!("Expected a rejection, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn a_5xx_is_a_retryable_provider_error() {
+        let error = send_email_answered_with(ResponseTemplate::new(503)).await;
+
+        assert!(matches!(
+            error,
+            EmailError::ProviderError { status: 503, .. }
+        ));
+        assert!(error.is_retryable());
+    }
+
+    #[tokio::test]
+    async fn an_unreachable_provider_is_a_retryable_connection_failure() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+        let subscriber_email = SubscriberEmail::parse(SafeEmail().fake()).unwrap();
+
+        let error = email_client(EmailProvider::Postmark, base_url)
+            .send_email(subscriber_email, "subject", "html", "text")
+            .await
+            .unwrap_err();
+
+        assert!(matches!(error, EmailError::ConnectionFailed(_)));
+        assert!(error.is_retryable());
+    }
+}
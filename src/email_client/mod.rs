@@ -0,0 +1,318 @@
+mod http;
+mod mailgun;
+mod postmark;
+mod rate_limit;
+mod sendgrid;
+mod ses;
+mod smtp;
+#[cfg(test)]
+mod test_helpers;
+
+pub use http::{EmailProvider, HttpApiTransport};
+pub use rate_limit::RateLimiter;
+pub use smtp::{SmtpAuthentication, SmtpCredentials, SmtpTls, SmtpTransport};
+
+use crate::domain::SubscriberEmail;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::time::Duration;
+
+pub struct EmailMessage<'a> {
+    pub sender: &'a SubscriberEmail,
+    pub recipient: &'a SubscriberEmail,
+    pub subject: &'a str,
+    pub html_content: &'a str,
+    pub text_content: &'a str,
+    pub headers: &'a [(&'a str, &'a str)],
+}
+
+#[derive(Debug, Clone)]
+pub struct BatchRecipient {
+    pub email: SubscriberEmail,
+    pub substitutions: BTreeMap<String, String>,
+}
+
+// `{{key}}` placeholders in the subject and bodies are replaced with each
+// recipient's substitutions.
+pub struct EmailBatch<'a> {
+    pub sender: &'a SubscriberEmail,
+    pub recipients: &'a [BatchRecipient],
+    pub subject: &'a str,
+    pub html_content: &'a str,
+    pub text_content: &'a str,
+}
+
+pub fn substitute(template: &str, substitutions: &BTreeMap<String, String>) -> String {
+    substitutions
+        .iter()
+        .fold(template.to_owned(), |content, (key, value)| {
+            content.replace(&format!("{{{{{}}}}}", key), value)
+        })
+}
+
+pub trait EmailTransport {
+    fn send(
+        &self,
+        message: &EmailMessage<'_>,
+    ) -> impl Future<Output = Result<(), EmailError>> + Send;
+
+    // Returns one result per recipient, in the order they were given.
+    fn send_batch(
+        &self,
+        batch: &EmailBatch<'_>,
+    ) -> impl Future<Output = Vec<Result<(), EmailError>>> + Send;
+}
+
+#[derive(serde::Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum EmailTransportKind {
+    Http,
+    Smtp,
+}
+
+#[derive(Clone)]
+pub enum EmailBackend {
+    Http(HttpApiTransport),
+    Smtp(SmtpTransport),
+}
+
+impl EmailTransport for EmailBackend {
+    async fn send(&self, message: &EmailMessage<'_>) -> Result<(), EmailError> {
+        match self {
+            Self::Http(transport) => transport.send(message).await,
+            Self::Smtp(transport) => transport.send(message).await,
+        }
+    }
+
+    async fn send_batch(&self, batch: &EmailBatch<'_>) -> Vec<Result<(), EmailError>> {
+        match self {
+            Self::Http(transport) => transport.send_batch(batch).await,
+            Self::Smtp(transport) => transport.send_batch(batch).await,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct EmailClient {
+    transport: EmailBackend,
+    sender: SubscriberEmail,
+    rate_limiter: Option<RateLimiter>,
+}
+
+impl EmailClient {
+    pub fn new(transport: EmailBackend, sender: SubscriberEmail) -> Self {
+        Self {
+            transport,
+            sender,
+            rate_limiter: None,
+        }
+    }
+
+    pub fn with_rate_limiter(mut self, rate_limiter: RateLimiter) -> Self {
+        self.rate_limiter = Some(rate_limiter);
+        self
+    }
+
+    pub async fn send_email(
+        &self,
+        recipient: SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Result<(), EmailError> {
+        self.send_email_with_headers(recipient, subject, html_content, text_content, &[])
+            .await
+    }
+
+    pub async fn send_email_with_headers(
+        &self,
+        recipient: SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[(&str, &str)],
+    ) -> Result<(), EmailError> {
+        let message = EmailMessage {
+            sender: &self.sender,
+            recipient: &recipient,
+            subject,
+            html_content,
+            text_content,
+            headers,
+        };
+        let _permit = self.acquire(1).await;
+        self.transport.send(&message).await
+    }
+
+    pub async fn send_batch(
+        &self,
+        recipients: &[BatchRecipient],
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Vec<Result<(), EmailError>> {
+        let batch = EmailBatch {
+            sender: &self.sender,
+            recipients,
+            subject,
+            html_content,
+            text_content,
+        };
+        let messages = u32::try_from(recipients.len()).unwrap_or(u32::MAX);
+        let _permit = self.acquire(messages).await;
+        self.transport.send_batch(&batch).await
+    }
+
+    async fn acquire(&self, messages: u32) -> Option<tokio::sync::OwnedSemaphorePermit> {
+        match &self.rate_limiter {
+            Some(rate_limiter) => Some(rate_limiter.acquire(messages).await),
+            None => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum EmailError {
+    Timeout(String),
+    ConnectionFailed(String),
+    RateLimited {
+        retry_after: Option<Duration>,
+        body: String,
+    },
+    // The provider refused this particular message; sending it again will
+    // not help.
+    Rejected {
+        status: u16,
+        message: String,
+        body: String,
+    },
+    ProviderError {
+        status: u16,
+        message: String,
+        body: String,
+    },
+    // The provider accepted the request but we could not tell from its
+    // response which messages went out, so some of them may have been sent.
+    MalformedResponse {
+        message: String,
+        body: String,
+    },
+    InvalidMessage(String),
+}
+
+impl EmailError {
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            EmailError::Timeout(_)
+            | EmailError::ConnectionFailed(_)
+            | EmailError::RateLimited { .. }
+            | EmailError::ProviderError { .. } => true,
+            // Sending the batch again could deliver duplicates.
+            EmailError::Rejected { .. }
+            | EmailError::MalformedResponse { .. }
+            | EmailError::InvalidMessage(_) => false,
+        }
+    }
+
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            EmailError::RateLimited { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+}
+
+impl std::error::Error for EmailError {}
+
+impl From<reqwest::Error> for EmailError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            Self::Timeout(e.to_string())
+        } else if e.is_builder() {
+            Self::InvalidMessage(e.to_string())
+        } else {
+            Self::ConnectionFailed(e.to_string())
+        }
+    }
+}
+
+impl From<lettre::transport::smtp::Error> for EmailError {
+    fn from(e: lettre::transport::smtp::Error) -> Self {
+        let status = e.status().map(u16::from).unwrap_or_default();
+        if e.is_timeout() {
+            Self::Timeout(e.to_string())
+        } else if e.is_permanent() {
+            Self::Rejected {
+                status,
+                message: e.to_string(),
+                body: String::new(),
+            }
+        } else if e.is_transient() {
+            Self::ProviderError {
+                status,
+                message: e.to_string(),
+                body: String::new(),
+            }
+        } else if e.is_client() {
+            Self::InvalidMessage(e.to_string())
+        } else {
+            Self::ConnectionFailed(e.to_string())
+        }
+    }
+}
+
+impl std::fmt::Display for EmailError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EmailError::Timeout(e) => write!(f, "Timed out sending the email: {}", e),
+            EmailError::ConnectionFailed(e) => {
+                write!(f, "Failed to reach the email provider: {}", e)
+            }
+            EmailError::RateLimited { retry_after, .. } => match retry_after {
+                Some(delay) => write!(
+                    f,
+                    "The email provider is rate limiting us, retry after {}s",
+                    delay.as_secs()
+                ),
+                None => write!(f, "The email provider is rate limiting us"),
+            },
+            EmailError::Rejected {
+                status, message, ..
+            } => write!(
+                f,
+                "The email provider rejected the request ({}): {}",
+                status, message
+            ),
+            EmailError::ProviderError {
+                status, message, ..
+            } => write!(f, "The email provider failed ({}): {}", status, message),
+            EmailError::MalformedResponse { message, .. } => write!(
+                f,
+                "The email provider sent a response we could not understand: {}",
+                message
+            ),
+            EmailError::InvalidMessage(e) => {
+                write!(f, "Failed to build the email: {}", e)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::email_client::substitute;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn substitute_replaces_every_known_placeholder() {
+        let substitutions = BTreeMap::from([
+            ("name".to_string(), "Ursula".to_string()),
+            ("link".to_string(), "https://x.y/z".to_string()),
+        ]);
+
+        let content =
+            substitute("Hi {{name}}, {{name}}! {{link}} {{other}}", &substitutions);
+
+        assert_eq!(content, "Hi Ursula, Ursula! https://x.y/z {{other}}");
+    }
+}
@@ -0,0 +1,273 @@
+use super::{substitute, BatchRecipient, EmailBatch, EmailError, EmailMessage};
+use reqwest::{Client, RequestBuilder};
+use secrecy::{ExposeSecret, Secret};
+
+pub(super) fn build_request(
+    client: &Client,
+    base_url: &str,
+    message: &EmailMessage<'_>,
+    authorization_token: Option<&Secret<String>>,
+) -> RequestBuilder {
+    let request_body = SendEmailRequest {
+        from: message.sender.as_ref(),
+        to: message.recipient.as_ref(),
+        subject: message.subject,
+        html_body: message.html_content,
+        text_body: message.text_content,
+        headers: message
+            .headers
+            .iter()
+            .map(|&(name, value)| EmailHeader { name, value })
+            .collect(),
+    };
+    let request = client
+        .post(format!("{}/email", base_url))
+        .json(&request_body);
+    match authorization_token {
+        Some(token) => request.header("X-Postmark-Server-Token", token.expose_secret()),
+        None => request,
+    }
+}
+
+pub(super) fn build_batch_request(
+    client: &Client,
+    base_url: &str,
+    batch: &EmailBatch<'_>,
+    recipients: &[BatchRecipient],
+    authorization_token: Option<&Secret<String>>,
+) -> RequestBuilder {
+    let request_body: Vec<_> = recipients
+        .iter()
+        .map(|recipient| BatchEmailRequest {
+            from: batch.sender.as_ref(),
+            to: recipient.email.as_ref(),
+            subject: substitute(batch.subject, &recipient.substitutions),
+            html_body: substitute(batch.html_content, &recipient.substitutions),
+            text_body: substitute(batch.text_content, &recipient.substitutions),
+        })
+        .collect();
+    let request = client
+        .post(format!("{}/email/batch", base_url))
+        .json(&request_body);
+    match authorization_token {
+        Some(token) => request.header("X-Postmark-Server-Token", token.expose_secret()),
+        None => request,
+    }
+}
+
+// Postmark answers a batch with a 200 and one status entry per message.
+pub(super) fn batch_results(
+    body: &str,
+    n_recipients: usize,
+) -> Vec<Result<(), EmailError>> {
+    let results: Vec<ErrorResponse> = match serde_json::from_str(body) {
+        Ok(results) => results,
+        Err(e) => {
+            let error = EmailError::MalformedResponse {
+                message: format!("Unexpected batch response: {}", e),
+                body: body.to_owned(),
+            };
+            return (0..n_recipients).map(|_| Err(error.clone())).collect();
+        }
+    };
+    results
+        .into_iter()
+        .map(|result| match result.error_code {
+            0 => Ok(()),
+            error_code => Err(EmailError::Rejected {
+                status: 422,
+                message: format!("{} (error code {})", result.message, error_code),
+                body: body.to_owned(),
+            }),
+        })
+        .collect()
+}
+
+pub(super) fn error_message(body: &str) -> Option<String> {
+    let error: ErrorResponse = serde_json::from_str(body).ok()?;
+    Some(format!(
+        "{} (error code {})",
+        error.message, error.error_code
+    ))
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct SendEmailRequest<'a> {
+    from: &'a str,
+    to: &'a str,
+    subject: &'a str,
+    html_body: &'a str,
+    text_body: &'a str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    headers: Vec<EmailHeader<'a>>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct BatchEmailRequest<'a> {
+    from: &'a str,
+    to: &'a str,
+    subject: String,
+    html_body: String,
+    text_body: String,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct EmailHeader<'a> {
+    name: &'a str,
+    value: &'a str,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ErrorResponse {
+    error_code: i64,
+    message: String,
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::email_client::test_helpers::{email_client, recipient, send};
+    use crate::email_client::{EmailError, EmailProvider};
+    use claim::{assert_err, assert_ok};
+    use wiremock::matchers::{body_json, header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn send_email_uses_the_postmark_request_format() {
+        let mock_server = MockServer::start().await;
+        Mock::given(path("/email"))
+            .and(method("POST"))
+            .and(header("X-Postmark-Server-Token", "server-token"))
+            .and(body_json(serde_json::json!({
+                "From": "sender@example.com",
+                "To": "recipient@example.com",
+                "Subject": "Subject",
+                "HtmlBody": "<p>Html</p>",
+                "TextBody": "Text",
+                "Headers": [{"Name": "List-Unsubscribe", "Value": "<https://x.y/z>"}]
+            })))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        assert_ok!(send(&email_client(EmailProvider::Postmark, mock_server.uri())).await);
+    }
+
+    #[tokio::test]
+    async fn send_email_reports_the_postmark_error_message() {
+        let mock_server = MockServer::start().await;
+        Mock::given(path("/email"))
+            .respond_with(ResponseTemplate::new(422).set_body_json(serde_json::json!({
+                "ErrorCode": 300,
+                "Message": "Invalid 'To' address."
+            })))
+            .mount(&mock_server)
+            .await;
+
+        let error = assert_err!(
+            send(&email_client(EmailProvider::Postmark, mock_server.uri())).await
+        );
+
+        assert!(error
+            .to_string()
+            .contains("Invalid 'To' address. (error code 300)"));
+    }
+
+    #[tokio::test]
+    async fn send_batch_returns_a_result_for_each_message_in_the_batch() {
+        let mock_server = MockServer::start().await;
+        Mock::given(path("/email/batch"))
+            .and(method("POST"))
+            .and(body_json(serde_json::json!([
+                {
+                    "From": "sender@example.com",
+                    "To": "ursula@example.com",
+                    "Subject": "Hi Ursula",
+                    "HtmlBody": "<p>Ursula</p>",
+                    "TextBody": "Ursula"
+                },
+                {
+                    "From": "sender@example.com",
+                    "To": "octavia@example.com",
+                    "Subject": "Hi Octavia",
+                    "HtmlBody": "<p>Octavia</p>",
+                    "TextBody": "Octavia"
+                }
+            ])))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+                {"ErrorCode": 0, "Message": "OK", "To": "ursula@example.com"},
+                {"ErrorCode": 406, "Message": "Inactive recipient"}
+            ])))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        let recipients = [
+            recipient("ursula@example.com", "Ursula"),
+            recipient("octavia@example.com", "Octavia"),
+        ];
+
+        let results = email_client(EmailProvider::Postmark, mock_server.uri())
+            .send_batch(&recipients, "Hi {{name}}", "<p>{{name}}</p>", "{{name}}")
+            .await;
+
+        assert_eq!(results.len(), 2);
+        assert_ok!(&results[0]);
+        let error = assert_err!(&results[1]);
+        assert!(!error.is_retryable());
+        assert!(error.to_string().contains("Inactive recipient"));
+    }
+
+    #[tokio::test]
+    async fn send_batch_fails_every_message_when_the_result_count_does_not_match() {
+        let mock_server = MockServer::start().await;
+        Mock::given(path("/email/batch"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+                {"ErrorCode": 0, "Message": "OK", "To": "ursula@example.com"}
+            ])))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        let recipients = [
+            recipient("ursula@example.com", "Ursula"),
+            recipient("octavia@example.com", "Octavia"),
+        ];
+
+        let results = email_client(EmailProvider::Postmark, mock_server.uri())
+            .send_batch(&recipients, "Hi {{name}}", "<p>{{name}}</p>", "{{name}}")
+            .await;
+
+        assert_eq!(results.len(), 2);
+        for result in &results {
+            let error = assert_err!(result);
+            assert!(matches!(error, EmailError::MalformedResponse { .. }));
+            assert!(!error.is_retryable());
+            assert!(error.to_string().contains("Expected 2 results"));
+        }
+    }
+
+    #[tokio::test]
+    async fn send_batch_does_not_retry_a_batch_response_it_cannot_parse() {
+        let mock_server = MockServer::start().await;
+        Mock::given(path("/email/batch"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("<html>OK</html>"))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        let recipients = [recipient("ursula@example.com", "Ursula")];
+
+        let results = email_client(EmailProvider::Postmark, mock_server.uri())
+            .send_batch(&recipients, "Hi {{name}}", "<p>{{name}}</p>", "{{name}}")
+            .await;
+
+        let error = assert_err!(&results[0]);
+        assert!(matches!(error, EmailError::MalformedResponse { .. }));
+        assert!(!error.is_retryable());
+    }
+}
@@ -0,0 +1,131 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::Instant;
+
+// Shared by every clone of the client, so the limits hold for the whole
+// process rather than per request handler.
+#[derive(Clone)]
+pub struct RateLimiter {
+    bucket: Arc<Mutex<TokenBucket>>,
+    in_flight: Arc<Semaphore>,
+}
+
+impl RateLimiter {
+    pub fn new(messages_per_second: u32, burst_size: u32, max_in_flight: usize) -> Self {
+        Self {
+            bucket: Arc::new(Mutex::new(TokenBucket::new(
+                messages_per_second,
+                burst_size,
+                Instant::now(),
+            ))),
+            in_flight: Arc::new(Semaphore::new(max_in_flight)),
+        }
+    }
+
+    // Waits until `messages` can be sent without exceeding the rate and a
+    // request slot is free. The slot is released when the permit is dropped.
+    pub async fn acquire(&self, messages: u32) -> OwnedSemaphorePermit {
+        let permit = Arc::clone(&self.in_flight)
+            .acquire_owned()
+            .await
+            .expect("The rate limiter semaphore is never closed.");
+        loop {
+            let wait = self.bucket.lock().unwrap().take(messages, Instant::now());
+            match wait {
+                None => return permit,
+                Some(delay) => tokio::time::sleep(delay).await,
+            }
+        }
+    }
+}
+
+struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    refill_per_second: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(messages_per_second: u32, burst_size: u32, now: Instant) -> Self {
+        let capacity = f64::from(burst_size);
+        Self {
+            capacity,
+            tokens: capacity,
+            refill_per_second: f64::from(messages_per_second),
+            last_refill: now,
+        }
+    }
+
+    // Takes the tokens, or returns how long to wait before trying again.
+    // A batch larger than the bucket is let through once the bucket is full
+    // and leaves it in debt, which delays whatever comes next.
+    fn take(&mut self, messages: u32, now: Instant) -> Option<Duration> {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.refill_per_second).min(self.capacity);
+        self.last_refill = now;
+        let needed = f64::from(messages).min(self.capacity);
+        if self.tokens >= needed {
+            self.tokens -= f64::from(messages);
+            return None;
+        }
+        Some(Duration::from_secs_f64(
+            (needed - self.tokens) / self.refill_per_second,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RateLimiter, TokenBucket};
+    use std::time::Duration;
+    use tokio::time::Instant;
+
+    #[tokio::test(start_paused = true)]
+    async fn a_full_bucket_allows_a_burst_then_refills_at_the_configured_rate() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(4, 3, now);
+
+        assert_eq!(bucket.take(3, now), None);
+        assert_eq!(bucket.take(1, now), Some(Duration::from_millis(250)));
+        assert_eq!(bucket.take(1, now + Duration::from_millis(250)), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_batch_larger_than_the_bucket_goes_into_debt() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(4, 2, now);
+
+        assert_eq!(bucket.take(6, now), None);
+        assert_eq!(bucket.take(1, now), Some(Duration::from_millis(1250)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn acquire_waits_for_the_bucket_to_refill() {
+        let rate_limiter = RateLimiter::new(4, 1, 5);
+        let start = Instant::now();
+
+        for _ in 0..3 {
+            drop(rate_limiter.acquire(1).await);
+        }
+
+        assert_eq!(start.elapsed(), Duration::from_millis(500));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn clones_share_the_in_flight_limit() {
+        let rate_limiter = RateLimiter::new(1000, 1000, 1);
+        let permit = rate_limiter.acquire(1).await;
+        let clone = rate_limiter.clone();
+        let waiting = tokio::spawn(async move { clone.acquire(1).await });
+
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert!(!waiting.is_finished());
+
+        drop(permit);
+        assert!(waiting.await.is_ok());
+    }
+}
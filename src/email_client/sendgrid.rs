@@ -0,0 +1,258 @@
+use super::{BatchRecipient, EmailBatch, EmailMessage};
+use reqwest::{Client, RequestBuilder};
+use secrecy::{ExposeSecret, Secret};
+use std::collections::BTreeMap;
+
+pub(super) fn build_request(
+    client: &Client,
+    base_url: &str,
+    message: &EmailMessage<'_>,
+    authorization_token: Option<&Secret<String>>,
+) -> RequestBuilder {
+    let request_body = SendEmailRequest {
+        personalizations: vec![Personalization {
+            to: vec![Address {
+                email: message.recipient.as_ref(),
+            }],
+            substitutions: BTreeMap::new(),
+        }],
+        from: Address {
+            email: message.sender.as_ref(),
+        },
+        subject: message.subject,
+        content: vec![
+            Content {
+                content_type: "text/plain",
+                value: message.text_content,
+            },
+            Content {
+                content_type: "text/html",
+                value: message.html_content,
+            },
+        ],
+        headers: message.headers.iter().copied().collect(),
+    };
+    let request = client
+        .post(format!("{}/v3/mail/send", base_url))
+        .json(&request_body);
+    match authorization_token {
+        Some(token) => request.bearer_auth(token.expose_secret()),
+        None => request,
+    }
+}
+
+// Placeholders are left in the content and replaced by SendGrid, one
+// personalization per recipient.
+pub(super) fn build_batch_request(
+    client: &Client,
+    base_url: &str,
+    batch: &EmailBatch<'_>,
+    recipients: &[BatchRecipient],
+    authorization_token: Option<&Secret<String>>,
+) -> RequestBuilder {
+    let request_body = SendEmailRequest {
+        personalizations: recipients
+            .iter()
+            .map(|recipient| Personalization {
+                to: vec![Address {
+                    email: recipient.email.as_ref(),
+                }],
+                substitutions: recipient
+                    .substitutions
+                    .iter()
+                    .map(|(key, value)| (format!("{{{{{}}}}}", key), value.as_str()))
+                    .collect(),
+            })
+            .collect(),
+        from: Address {
+            email: batch.sender.as_ref(),
+        },
+        subject: batch.subject,
+        content: vec![
+            Content {
+                content_type: "text/plain",
+                value: batch.text_content,
+            },
+            Content {
+                content_type: "text/html",
+                value: batch.html_content,
+            },
+        ],
+        headers: BTreeMap::new(),
+    };
+    let request = client
+        .post(format!("{}/v3/mail/send", base_url))
+        .json(&request_body);
+    match authorization_token {
+        Some(token) => request.bearer_auth(token.expose_secret()),
+        None => request,
+    }
+}
+
+pub(super) fn error_message(body: &str) -> Option<String> {
+    let response: ErrorResponse = serde_json::from_str(body).ok()?;
+    let messages: Vec<_> = response.errors.into_iter().map(|e| e.message).collect();
+    if messages.is_empty() {
+        return None;
+    }
+    Some(messages.join("; "))
+}
+
+#[derive(serde::Serialize)]
+struct SendEmailRequest<'a> {
+    personalizations: Vec<Personalization<'a>>,
+    from: Address<'a>,
+    subject: &'a str,
+    content: Vec<Content<'a>>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    headers: BTreeMap<&'a str, &'a str>,
+}
+
+#[derive(serde::Serialize)]
+struct Personalization<'a> {
+    to: Vec<Address<'a>>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    substitutions: BTreeMap<String, &'a str>,
+}
+
+#[derive(serde::Serialize)]
+struct Address<'a> {
+    email: &'a str,
+}
+
+// SendGrid requires text/plain to come before text/html.
+#[derive(serde::Serialize)]
+struct Content<'a> {
+    #[serde(rename = "type")]
+    content_type: &'a str,
+    value: &'a str,
+}
+
+#[derive(serde::Deserialize)]
+struct ErrorResponse {
+    errors: Vec<ErrorDetail>,
+}
+
+#[derive(serde::Deserialize)]
+struct ErrorDetail {
+    message: String,
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::email_client::test_helpers::{email_client, recipient, send};
+    use crate::email_client::EmailProvider;
+    use claim::{assert_err, assert_ok};
+    use wiremock::matchers::{body_json, header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn send_email_uses_the_sendgrid_request_format() {
+        let mock_server = MockServer::start().await;
+        Mock::given(path("/v3/mail/send"))
+            .and(method("POST"))
+            .and(header("Authorization", "Bearer server-token"))
+            .and(body_json(serde_json::json!({
+                "personalizations": [{"to": [{"email": "recipient@example.com"}]}],
+                "from": {"email": "sender@example.com"},
+                "subject": "Subject",
+                "content": [
+                    {"type": "text/plain", "value": "Text"},
+                    {"type": "text/html", "value": "<p>Html</p>"}
+                ],
+                "headers": {"List-Unsubscribe": "<https://x.y/z>"}
+            })))
+            .respond_with(ResponseTemplate::new(202))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        assert_ok!(send(&email_client(EmailProvider::SendGrid, mock_server.uri())).await);
+    }
+
+    #[tokio::test]
+    async fn send_email_reports_the_sendgrid_error_messages() {
+        let mock_server = MockServer::start().await;
+        Mock::given(path("/v3/mail/send"))
+            .respond_with(ResponseTemplate::new(400).set_body_json(serde_json::json!({
+                "errors": [
+                    {"message": "Invalid recipient", "field": "personalizations.0.to"},
+                    {"message": "Missing subject", "field": "subject"}
+                ]
+            })))
+            .mount(&mock_server)
+            .await;
+
+        let error = assert_err!(
+            send(&email_client(EmailProvider::SendGrid, mock_server.uri())).await
+        );
+
+        assert!(error
+            .to_string()
+            .contains("Invalid recipient; Missing subject"));
+    }
+
+    #[tokio::test]
+    async fn send_batch_uses_one_personalization_per_recipient() {
+        let mock_server = MockServer::start().await;
+        Mock::given(path("/v3/mail/send"))
+            .and(method("POST"))
+            .and(body_json(serde_json::json!({
+                "personalizations": [
+                    {
+                        "to": [{"email": "ursula@example.com"}],
+                        "substitutions": {"{{name}}": "Ursula"}
+                    },
+                    {
+                        "to": [{"email": "octavia@example.com"}],
+                        "substitutions": {"{{name}}": "Octavia"}
+                    }
+                ],
+                "from": {"email": "sender@example.com"},
+                "subject": "Hi {{name}}",
+                "content": [
+                    {"type": "text/plain", "value": "{{name}}"},
+                    {"type": "text/html", "value": "<p>{{name}}</p>"}
+                ]
+            })))
+            .respond_with(ResponseTemplate::new(202))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        let recipients = [
+            recipient("ursula@example.com", "Ursula"),
+            recipient("octavia@example.com", "Octavia"),
+        ];
+
+        let results = email_client(EmailProvider::SendGrid, mock_server.uri())
+            .send_batch(&recipients, "Hi {{name}}", "<p>{{name}}</p>", "{{name}}")
+            .await;
+
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(|r| r.is_ok()));
+    }
+
+    #[tokio::test]
+    async fn a_failed_batch_request_fails_every_recipient() {
+        let mock_server = MockServer::start().await;
+        Mock::given(path("/v3/mail/send"))
+            .respond_with(ResponseTemplate::new(503))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        let recipients = [
+            recipient("ursula@example.com", "Ursula"),
+            recipient("octavia@example.com", "Octavia"),
+        ];
+
+        let results = email_client(EmailProvider::SendGrid, mock_server.uri())
+            .send_batch(&recipients, "Hi {{name}}", "<p>{{name}}</p>", "{{name}}")
+            .await;
+
+        assert_eq!(results.len(), 2);
+        assert!(results
+            .iter()
+            .all(|r| matches!(r, Err(e) if e.is_retryable())));
+    }
+}
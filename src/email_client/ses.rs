@@ -0,0 +1,542 @@
+use super::{BatchRecipient, EmailBatch, EmailError, EmailMessage};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use reqwest::header::CONTENT_TYPE;
+use reqwest::{Client, RequestBuilder};
+use secrecy::{ExposeSecret, Secret};
+use sha2::{Digest, Sha256};
+
+// Amazon SES v2 `SendEmail` with simple content.
+pub(super) fn build_request(
+    client: &Client,
+    base_url: &str,
+    message: &EmailMessage<'_>,
+    authorization_token: Option<&Secret<String>>,
+) -> RequestBuilder {
+    let request_body = SendEmailRequest {
+        from_email_address: message.sender.as_ref(),
+        destination: Destination {
+            to_addresses: vec![message.recipient.as_ref()],
+        },
+        content: Content {
+            simple: SimpleContent {
+                subject: Data {
+                    data: message.subject,
+                },
+                body: Body {
+                    text: Data {
+                        data: message.text_content,
+                    },
+                    html: Data {
+                        data: message.html_content,
+                    },
+                },
+                headers: message
+                    .headers
+                    .iter()
+                    .map(|&(name, value)| EmailHeader { name, value })
+                    .collect(),
+            },
+        },
+    };
+    let body = serde_json::to_vec(&request_body).expect("Failed to serialize the email.");
+    signed_request(client, base_url, SEND_EMAIL_PATH, body, authorization_token)
+}
+
+// SES v2 `SendBulkEmail` with an inline template: SES renders the `{{key}}`
+// placeholders itself from each entry's replacement data.
+pub(super) fn build_batch_request(
+    client: &Client,
+    base_url: &str,
+    batch: &EmailBatch<'_>,
+    recipients: &[BatchRecipient],
+    authorization_token: Option<&Secret<String>>,
+) -> RequestBuilder {
+    let request_body = SendBulkEmailRequest {
+        from_email_address: batch.sender.as_ref(),
+        default_content: BulkDefaultContent {
+            template: Template {
+                template_content: TemplateContent {
+                    subject: batch.subject,
+                    text: batch.text_content,
+                    html: batch.html_content,
+                },
+                template_data: "{}",
+            },
+        },
+        bulk_email_entries: recipients
+            .iter()
+            .map(|recipient| BulkEmailEntry {
+                destination: Destination {
+                    to_addresses: vec![recipient.email.as_ref()],
+                },
+                replacement_email_content: ReplacementEmailContent {
+                    replacement_template: ReplacementTemplate {
+                        replacement_template_data: serde_json::to_string(
+                            &recipient.substitutions,
+                        )
+                        .expect("Failed to serialize the substitutions."),
+                    },
+                },
+            })
+            .collect(),
+    };
+    let body = serde_json::to_vec(&request_body).expect("Failed to serialize the email.");
+    signed_request(
+        client,
+        base_url,
+        SEND_BULK_EMAIL_PATH,
+        body,
+        authorization_token,
+    )
+}
+
+pub(super) fn batch_results(
+    body: &str,
+    n_recipients: usize,
+) -> Vec<Result<(), EmailError>> {
+    let response: SendBulkEmailResponse = match serde_json::from_str(body) {
+        Ok(response) => response,
+        Err(e) => {
+            let error = EmailError::MalformedResponse {
+                message: format!("Unexpected batch response: {}", e),
+                body: body.to_owned(),
+            };
+            return (0..n_recipients).map(|_| Err(error.clone())).collect();
+        }
+    };
+    response
+        .bulk_email_entry_results
+        .into_iter()
+        .map(|result| {
+            let message =
+                format!("{}: {}", result.status, result.error.unwrap_or_default());
+            match result.status.as_str() {
+                "SUCCESS" => Ok(()),
+                "ACCOUNT_THROTTLED" | "ACCOUNT_DAILY_QUOTA_EXCEEDED" => {
+                    Err(EmailError::RateLimited {
+                        retry_after: None,
+                        body: body.to_owned(),
+                    })
+                }
+                "TRANSIENT_FAILURE" | "FAILED" => Err(EmailError::ProviderError {
+                    status: 200,
+                    message,
+                    body: body.to_owned(),
+                }),
+                _ => Err(EmailError::Rejected {
+                    status: 400,
+                    message,
+                    body: body.to_owned(),
+                }),
+            }
+        })
+        .collect()
+}
+
+fn signed_request(
+    client: &Client,
+    base_url: &str,
+    path: &str,
+    body: Vec<u8>,
+    authorization_token: Option<&Secret<String>>,
+) -> RequestBuilder {
+    let url = format!("{}{}", base_url, path);
+    let request = client.post(&url).header(CONTENT_TYPE, "application/json");
+    let request = match authorization_token {
+        Some(token) => {
+            let now = Utc::now();
+            let host = reqwest::Url::parse(&url)
+                .ok()
+                .and_then(|url| {
+                    let host = url.host_str()?.to_owned();
+                    Some(match url.port() {
+                        Some(port) => format!("{}:{}", host, port),
+                        None => host,
+                    })
+                })
+                .unwrap_or_default();
+            let authorization = authorization_header(token, &host, path, &body, now);
+            request
+                .header("X-Amz-Date", now.format(AMZ_DATE_FORMAT).to_string())
+                .header("Authorization", authorization)
+        }
+        None => request,
+    };
+    request.body(body)
+}
+
+const SEND_EMAIL_PATH: &str = "/v2/email/outbound-emails";
+const SEND_BULK_EMAIL_PATH: &str = "/v2/email/outbound-bulk-emails";
+const AMZ_DATE_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+// SES has no static API token: requests are signed with AWS Signature
+// Version 4, using a token of the form `<access key id>:<secret access key>`.
+// The region is read from the `email.<region>.amazonaws.com` endpoint.
+fn authorization_header(
+    token: &Secret<String>,
+    host: &str,
+    path: &str,
+    body: &[u8],
+    now: DateTime<Utc>,
+) -> String {
+    let (access_key_id, secret_access_key) = token
+        .expose_secret()
+        .split_once(':')
+        .unwrap_or((token.expose_secret(), ""));
+    let region = host
+        .strip_prefix("email.")
+        .and_then(|host| host.split('.').next())
+        .unwrap_or("us-east-1");
+    let amz_date = now.format(AMZ_DATE_FORMAT).to_string();
+    let date = now.format("%Y%m%d").to_string();
+    let signed_headers = "content-type;host;x-amz-date";
+    let canonical_request = format!(
+        "POST\n{}\n\ncontent-type:application/json\nhost:{}\nx-amz-date:{}\n\n{}\n{}",
+        path,
+        host,
+        amz_date,
+        signed_headers,
+        hex::encode(Sha256::digest(body))
+    );
+    let scope = format!("{}/{}/ses/aws4_request", date, region);
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        amz_date,
+        scope,
+        hex::encode(Sha256::digest(canonical_request))
+    );
+    let signing_key = signing_key(secret_access_key, &date, region, "ses");
+    let signature = hex::encode(hmac_sha256(&signing_key, string_to_sign.as_bytes()));
+    format!(
+        "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+        access_key_id, scope, signed_headers, signature
+    )
+}
+
+fn signing_key(
+    secret_access_key: &str,
+    date: &str,
+    region: &str,
+    service: &str,
+) -> Vec<u8> {
+    let key = format!("AWS4{}", secret_access_key);
+    let key = hmac_sha256(key.as_bytes(), date.as_bytes());
+    let key = hmac_sha256(&key, region.as_bytes());
+    let key = hmac_sha256(&key, service.as_bytes());
+    hmac_sha256(&key, b"aws4_request")
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length.");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+pub(super) fn error_message(body: &str) -> Option<String> {
+    let error: ErrorResponse = serde_json::from_str(body).ok()?;
+    Some(error.message)
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct SendEmailRequest<'a> {
+    from_email_address: &'a str,
+    destination: Destination<'a>,
+    content: Content<'a>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct SendBulkEmailRequest<'a> {
+    from_email_address: &'a str,
+    default_content: BulkDefaultContent<'a>,
+    bulk_email_entries: Vec<BulkEmailEntry<'a>>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct BulkDefaultContent<'a> {
+    template: Template<'a>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct Template<'a> {
+    template_content: TemplateContent<'a>,
+    template_data: &'a str,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct TemplateContent<'a> {
+    subject: &'a str,
+    text: &'a str,
+    html: &'a str,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct BulkEmailEntry<'a> {
+    destination: Destination<'a>,
+    replacement_email_content: ReplacementEmailContent,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct ReplacementEmailContent {
+    replacement_template: ReplacementTemplate,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct ReplacementTemplate {
+    replacement_template_data: String,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct SendBulkEmailResponse {
+    bulk_email_entry_results: Vec<BulkEmailEntryResult>,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct BulkEmailEntryResult {
+    status: String,
+    error: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct Destination<'a> {
+    to_addresses: Vec<&'a str>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct Content<'a> {
+    simple: SimpleContent<'a>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct SimpleContent<'a> {
+    subject: Data<'a>,
+    body: Body<'a>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    headers: Vec<EmailHeader<'a>>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct Body<'a> {
+    text: Data<'a>,
+    html: Data<'a>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct Data<'a> {
+    data: &'a str,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct EmailHeader<'a> {
+    name: &'a str,
+    value: &'a str,
+}
+
+#[derive(serde::Deserialize)]
+struct ErrorResponse {
+    #[serde(alias = "Message")]
+    message: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::email_client::test_helpers::{email_client, recipient, send};
+    use crate::email_client::{BatchRecipient, EmailProvider};
+    use claim::{assert_err, assert_ok};
+    use wiremock::matchers::{
+        body_json, body_partial_json, header, header_regex, method, path,
+    };
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn send_email_uses_the_ses_request_format() {
+        let mock_server = MockServer::start().await;
+        Mock::given(path("/v2/email/outbound-emails"))
+            .and(method("POST"))
+            .and(header("Content-Type", "application/json"))
+            .and(header_regex("X-Amz-Date", r"^\d{8}T\d{6}Z$"))
+            .and(header_regex(
+                "Authorization",
+                "^AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/\\d{8}/us-east-1/ses/aws4_request, \
+                SignedHeaders=content-type;host;x-amz-date, Signature=[0-9a-f]{64}$",
+            ))
+            .and(body_json(serde_json::json!({
+                "FromEmailAddress": "sender@example.com",
+                "Destination": {"ToAddresses": ["recipient@example.com"]},
+                "Content": {
+                    "Simple": {
+                        "Subject": {"Data": "Subject"},
+                        "Body": {
+                            "Text": {"Data": "Text"},
+                            "Html": {"Data": "<p>Html</p>"}
+                        },
+                        "Headers": [
+                            {"Name": "List-Unsubscribe", "Value": "<https://x.y/z>"}
+                        ]
+                    }
+                }
+            })))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        assert_ok!(send(&email_client(EmailProvider::Ses, mock_server.uri())).await);
+    }
+
+    #[tokio::test]
+    async fn send_email_reports_the_ses_error_message() {
+        let mock_server = MockServer::start().await;
+        Mock::given(path("/v2/email/outbound-emails"))
+            .respond_with(
+                ResponseTemplate::new(400)
+                    .insert_header("x-amzn-ErrorType", "MessageRejected")
+                    .set_body_json(serde_json::json!({
+                        "message": "Email address is not verified."
+                    })),
+            )
+            .mount(&mock_server)
+            .await;
+
+        let error =
+            assert_err!(send(&email_client(EmailProvider::Ses, mock_server.uri())).await);
+
+        assert!(error.to_string().contains("Email address is not verified."));
+    }
+
+    fn recipients(n: usize) -> Vec<BatchRecipient> {
+        (0..n)
+            .map(|i| {
+                recipient(
+                    &format!("reader{}@example.com", i),
+                    &format!("Reader {}", i),
+                )
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn send_batch_uses_the_ses_bulk_format_with_an_inline_template() {
+        let mock_server = MockServer::start().await;
+        Mock::given(path("/v2/email/outbound-bulk-emails"))
+            .and(method("POST"))
+            .and(body_json(serde_json::json!({
+                "FromEmailAddress": "sender@example.com",
+                "DefaultContent": {
+                    "Template": {
+                        "TemplateContent": {
+                            "Subject": "Hi {{name}}",
+                            "Text": "{{name}}",
+                            "Html": "<p>{{name}}</p>"
+                        },
+                        "TemplateData": "{}"
+                    }
+                },
+                "BulkEmailEntries": [
+                    {
+                        "Destination": {"ToAddresses": ["reader0@example.com"]},
+                        "ReplacementEmailContent": {
+                            "ReplacementTemplate": {
+                                "ReplacementTemplateData": "{\"name\":\"Reader 0\"}"
+                            }
+                        }
+                    },
+                    {
+                        "Destination": {"ToAddresses": ["reader1@example.com"]},
+                        "ReplacementEmailContent": {
+                            "ReplacementTemplate": {
+                                "ReplacementTemplateData": "{\"name\":\"Reader 1\"}"
+                            }
+                        }
+                    }
+                ]
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "BulkEmailEntryResults": [
+                    {"Status": "SUCCESS", "MessageId": "1"},
+                    {"Status": "MESSAGE_REJECTED", "Error": "Address blacklisted."}
+                ]
+            })))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let results = email_client(EmailProvider::Ses, mock_server.uri())
+            .send_batch(&recipients(2), "Hi {{name}}", "<p>{{name}}</p>", "{{name}}")
+            .await;
+
+        assert_eq!(results.len(), 2);
+        assert_ok!(&results[0]);
+        let error = assert_err!(&results[1]);
+        assert!(!error.is_retryable());
+        assert!(error.to_string().contains("Address blacklisted."));
+    }
+
+    #[tokio::test]
+    async fn send_batch_splits_recipients_by_the_maximum_batch_size() {
+        let mock_server = MockServer::start().await;
+        let successes = |n: usize| {
+            let results: Vec<_> = (0..n)
+                .map(|_| serde_json::json!({"Status": "SUCCESS"}))
+                .collect();
+            serde_json::json!({"BulkEmailEntryResults": results})
+        };
+        Mock::given(path("/v2/email/outbound-bulk-emails"))
+            .and(body_partial_json(serde_json::json!({
+                "BulkEmailEntries": [{"Destination": {"ToAddresses": ["reader50@example.com"]}}]
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(successes(10)))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(path("/v2/email/outbound-bulk-emails"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(successes(50)))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let results = email_client(EmailProvider::Ses, mock_server.uri())
+            .send_batch(
+                &recipients(60),
+                "Hi {{name}}",
+                "<p>{{name}}</p>",
+                "{{name}}",
+            )
+            .await;
+
+        assert_eq!(results.len(), 60);
+        assert!(results.iter().all(|r| r.is_ok()));
+    }
+
+    // Example from the AWS documentation on deriving a SigV4 signing key.
+    #[test]
+    fn signing_key_matches_the_aws_example() {
+        let key = super::signing_key(
+            "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+            "20120215",
+            "us-east-1",
+            "iam",
+        );
+        assert_eq!(
+            hex::encode(key),
+            "f4780e2d9f65fa895f9c67b32ce1baf0b0d8a43505a000a1a9e090d414db404d"
+        );
+    }
+}
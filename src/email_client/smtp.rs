@@ -0,0 +1,387 @@
+use super::{substitute, EmailBatch, EmailError, EmailMessage, EmailTransport};
+use lettre::message::header::{HeaderName, HeaderValue};
+use lettre::message::{Mailbox, MultiPart};
+use lettre::transport::smtp::authentication::{Credentials, Mechanism};
+use lettre::transport::smtp::{SMTP_PORT, SUBMISSIONS_PORT, SUBMISSION_PORT};
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use secrecy::{ExposeSecret, Secret};
+use std::time::Duration;
+
+#[derive(serde::Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpTls {
+    // Plaintext, only meant for local relays and tests.
+    None,
+    StartTls,
+    Implicit,
+}
+
+impl SmtpTls {
+    // Used when the configuration does not name a port.
+    pub fn default_port(self) -> u16 {
+        match self {
+            SmtpTls::None => SMTP_PORT,
+            SmtpTls::StartTls => SUBMISSION_PORT,
+            SmtpTls::Implicit => SUBMISSIONS_PORT,
+        }
+    }
+}
+
+#[derive(serde::Deserialize, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpAuthentication {
+    #[default]
+    Plain,
+    Login,
+}
+
+impl From<SmtpAuthentication> for Mechanism {
+    fn from(authentication: SmtpAuthentication) -> Self {
+        match authentication {
+            SmtpAuthentication::Plain => Mechanism::Plain,
+            SmtpAuthentication::Login => Mechanism::Login,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct SmtpCredentials {
+    pub username: String,
+    pub password: Secret<String>,
+    pub authentication: SmtpAuthentication,
+}
+
+#[derive(Clone)]
+pub struct SmtpTransport {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+impl SmtpTransport {
+    pub fn new(
+        host: &str,
+        port: Option<u16>,
+        tls: SmtpTls,
+        credentials: Option<SmtpCredentials>,
+        timeout: Duration,
+    ) -> Result<Self, EmailError> {
+        let mut builder = match tls {
+            SmtpTls::None => {
+                AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host)
+            }
+            SmtpTls::StartTls => {
+                AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)?
+            }
+            SmtpTls::Implicit => AsyncSmtpTransport::<Tokio1Executor>::relay(host)?,
+        }
+        .port(port.unwrap_or(tls.default_port()))
+        .timeout(Some(timeout));
+        if let Some(credentials) = credentials {
+            builder = builder
+                .credentials(Credentials::new(
+                    credentials.username,
+                    credentials.password.expose_secret().to_owned(),
+                ))
+                .authentication(vec![credentials.authentication.into()]);
+        }
+        Ok(Self {
+            transport: builder.build(),
+        })
+    }
+}
+
+impl EmailTransport for SmtpTransport {
+    async fn send(&self, message: &EmailMessage<'_>) -> Result<(), EmailError> {
+        let message = build_message(message)?;
+        self.transport.send(message).await?;
+        Ok(())
+    }
+
+    // SMTP has no batch submission, so every recipient gets its own message.
+    async fn send_batch(&self, batch: &EmailBatch<'_>) -> Vec<Result<(), EmailError>> {
+        let mut results = Vec::with_capacity(batch.recipients.len());
+        for recipient in batch.recipients {
+            let subject = substitute(batch.subject, &recipient.substitutions);
+            let html_content = substitute(batch.html_content, &recipient.substitutions);
+            let text_content = substitute(batch.text_content, &recipient.substitutions);
+            let message = EmailMessage {
+                sender: batch.sender,
+                recipient: &recipient.email,
+                subject: &subject,
+                html_content: &html_content,
+                text_content: &text_content,
+                headers: &[],
+            };
+            results.push(self.send(&message).await);
+        }
+        results
+    }
+}
+
+fn build_message(message: &EmailMessage<'_>) -> Result<Message, EmailError> {
+    let from: Mailbox = message
+        .sender
+        .as_ref()
+        .parse()
+        .map_err(|e| EmailError::InvalidMessage(format!("{}", e)))?;
+    let to: Mailbox = message
+        .recipient
+        .as_ref()
+        .parse()
+        .map_err(|e| EmailError::InvalidMessage(format!("{}", e)))?;
+    let mut builder = Message::builder()
+        .from(from)
+        .to(to)
+        .subject(message.subject);
+    for &(name, value) in message.headers {
+        let name = HeaderName::new_from_ascii(name.to_owned())
+            .map_err(|e| EmailError::InvalidMessage(format!("{}", e)))?;
+        builder = builder.raw_header(HeaderValue::new(name, value.to_owned()));
+    }
+    builder
+        .multipart(MultiPart::alternative_plain_html(
+            message.text_content.to_owned(),
+            message.html_content.to_owned(),
+        ))
+        .map_err(|e| EmailError::InvalidMessage(format!("{}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::{
+        BatchRecipient, EmailBackend, EmailClient, SmtpAuthentication, SmtpCredentials,
+        SmtpTls, SmtpTransport,
+    };
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+    use claim::{assert_err, assert_ok};
+    use secrecy::Secret;
+    use std::collections::BTreeMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    // An SMTP server that accepts everything except recipients at
+    // `rejected.example.com`, and records the lines it received.
+    async fn spawn_smtp_stub() -> (u16, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let transcript = Arc::new(Mutex::new(Vec::new()));
+        let received = Arc::clone(&transcript);
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let (reader, mut writer) = stream.into_split();
+                let mut lines = BufReader::new(reader).lines();
+                writer.write_all(b"220 stub ESMTP\r\n").await.unwrap();
+                let mut in_data = false;
+                let mut login_step = 0;
+                while let Ok(Some(line)) = lines.next_line().await {
+                    received.lock().unwrap().push(line.clone());
+                    let reply: &[u8] = if in_data {
+                        if line != "." {
+                            continue;
+                        }
+                        in_data = false;
+                        b"250 Queued\r\n"
+                    } else if login_step == 1 {
+                        login_step = 2;
+                        b"334 UGFzc3dvcmQ6\r\n"
+                    } else if login_step == 2 {
+                        login_step = 0;
+                        b"235 Authenticated\r\n"
+                    } else if line.starts_with("RCPT TO") && line.contains("@rejected.") {
+                        b"550 No such user\r\n"
+                    } else if line.starts_with("EHLO") {
+                        b"250-stub\r\n250 AUTH PLAIN LOGIN\r\n"
+                    } else if line == "AUTH LOGIN" {
+                        login_step = 1;
+                        b"334 VXNlcm5hbWU6\r\n"
+                    } else if line.starts_with("AUTH PLAIN") {
+                        b"235 Authenticated\r\n"
+                    } else if line == "DATA" {
+                        in_data = true;
+                        b"354 Go ahead\r\n"
+                    } else if line == "QUIT" {
+                        writer.write_all(b"221 Bye\r\n").await.unwrap();
+                        break;
+                    } else {
+                        b"250 OK\r\n"
+                    };
+                    writer.write_all(reply).await.unwrap();
+                }
+            }
+        });
+        (port, transcript)
+    }
+
+    fn email_client(port: u16, credentials: Option<SmtpCredentials>) -> EmailClient {
+        let transport = SmtpTransport::new(
+            "127.0.0.1",
+            Some(port),
+            SmtpTls::None,
+            credentials,
+            timeout(),
+        )
+        .unwrap();
+        let sender = SubscriberEmail::parse("sender@example.com".into()).unwrap();
+        EmailClient::new(EmailBackend::Smtp(transport), sender)
+    }
+
+    fn timeout() -> std::time::Duration {
+        std::time::Duration::from_secs(10)
+    }
+
+    fn credentials(authentication: SmtpAuthentication) -> Option<SmtpCredentials> {
+        Some(SmtpCredentials {
+            username: "user".into(),
+            password: Secret::new("secret".into()),
+            authentication,
+        })
+    }
+
+    async fn send(email_client: &EmailClient) -> Result<(), super::EmailError> {
+        let recipient = SubscriberEmail::parse("recipient@example.com".into()).unwrap();
+        email_client
+            .send_email_with_headers(
+                recipient,
+                "Subject line",
+                "<p>Html body</p>",
+                "Text body",
+                &[("List-Unsubscribe", "<https://x.y/z>")],
+            )
+            .await
+    }
+
+    #[tokio::test]
+    async fn send_email_delivers_the_message_over_smtp() {
+        let (port, transcript) = spawn_smtp_stub().await;
+        let email_client = email_client(port, None);
+
+        assert_ok!(send(&email_client).await);
+
+        let transcript = transcript.lock().unwrap().join("\n");
+        assert!(transcript.contains("MAIL FROM:<sender@example.com>"));
+        assert!(transcript.contains("RCPT TO:<recipient@example.com>"));
+        assert!(transcript.contains("Subject: Subject line"));
+        assert!(transcript.contains("List-Unsubscribe: <https://x.y/z>"));
+        assert!(transcript.contains("Text body"));
+        assert!(!transcript.contains("AUTH"));
+    }
+
+    #[tokio::test]
+    async fn send_email_authenticates_with_auth_plain() {
+        let (port, transcript) = spawn_smtp_stub().await;
+        let email_client = email_client(port, credentials(SmtpAuthentication::Plain));
+
+        assert_ok!(send(&email_client).await);
+
+        let expected = format!("AUTH PLAIN {}", STANDARD.encode("\0user\0secret"));
+        assert!(transcript.lock().unwrap().contains(&expected));
+    }
+
+    #[tokio::test]
+    async fn send_email_authenticates_with_auth_login() {
+        let (port, transcript) = spawn_smtp_stub().await;
+        let email_client = email_client(port, credentials(SmtpAuthentication::Login));
+
+        assert_ok!(send(&email_client).await);
+
+        let transcript = transcript.lock().unwrap();
+        let login = transcript.iter().position(|l| l == "AUTH LOGIN").unwrap();
+        assert_eq!(transcript[login + 1], STANDARD.encode("user"));
+        assert_eq!(transcript[login + 2], STANDARD.encode("secret"));
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_if_the_server_is_unreachable() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+        let email_client = email_client(port, None);
+
+        assert_err!(send(&email_client).await);
+    }
+
+    #[tokio::test]
+    async fn send_batch_sends_one_message_per_recipient() {
+        let (port, transcript) = spawn_smtp_stub().await;
+        let email_client = email_client(port, None);
+        let recipient = |email: &str, name: &str| BatchRecipient {
+            email: SubscriberEmail::parse(email.into()).unwrap(),
+            substitutions: BTreeMap::from([("name".to_string(), name.to_string())]),
+        };
+        let recipients = [
+            recipient("ursula@example.com", "Ursula"),
+            recipient("nobody@rejected.example.com", "Nobody"),
+        ];
+
+        let results = email_client
+            .send_batch(&recipients, "Hi {{name}}", "<p>{{name}}</p>", "{{name}}")
+            .await;
+
+        assert_ok!(&results[0]);
+        let error = assert_err!(&results[1]);
+        assert!(!error.is_retryable());
+        let transcript = transcript.lock().unwrap();
+        assert!(transcript.contains(&"Subject: Hi Ursula".to_string()));
+        assert!(transcript.contains(&"RCPT TO:<nobody@rejected.example.com>".to_string()));
+    }
+
+    // Counts the connections it accepts and closes them straight away.
+    async fn spawn_connection_counter() -> (u16, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let connections = Arc::new(AtomicUsize::new(0));
+        let accepted = Arc::clone(&connections);
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                accepted.fetch_add(1, Ordering::SeqCst);
+                drop(stream);
+            }
+        });
+        (port, connections)
+    }
+
+    fn tls_email_client(port: Option<u16>, tls: SmtpTls) -> EmailClient {
+        let transport =
+            SmtpTransport::new("localhost", port, tls, None, timeout()).unwrap();
+        let sender = SubscriberEmail::parse("sender@example.com".into()).unwrap();
+        EmailClient::new(EmailBackend::Smtp(transport), sender)
+    }
+
+    #[test]
+    fn each_tls_mode_has_its_conventional_default_port() {
+        assert_eq!(SmtpTls::None.default_port(), 25);
+        assert_eq!(SmtpTls::StartTls.default_port(), 587);
+        assert_eq!(SmtpTls::Implicit.default_port(), 465);
+    }
+
+    #[tokio::test]
+    async fn starttls_transport_connects_to_the_configured_port() {
+        let (port, connections) = spawn_connection_counter().await;
+        let email_client = tls_email_client(Some(port), SmtpTls::StartTls);
+
+        // The stub speaks no TLS, so only the connection itself succeeds.
+        assert_err!(send(&email_client).await);
+
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn implicit_tls_transport_connects_to_the_configured_port() {
+        let (port, connections) = spawn_connection_counter().await;
+        let email_client = tls_email_client(Some(port), SmtpTls::Implicit);
+
+        assert_err!(send(&email_client).await);
+
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn tls_transports_build_with_their_default_ports() {
+        for tls in [SmtpTls::StartTls, SmtpTls::Implicit] {
+            assert_ok!(SmtpTransport::new("localhost", None, tls, None, timeout()));
+        }
+    }
+}
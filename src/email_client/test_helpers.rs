@@ -0,0 +1,44 @@
+use crate::domain::SubscriberEmail;
+use crate::email_client::{
+    BatchRecipient, EmailBackend, EmailClient, EmailError, EmailProvider,
+    HttpApiTransport,
+};
+use secrecy::Secret;
+use std::collections::BTreeMap;
+
+// SES signs with an `<access key id>:<secret>` pair, the others send the
+// token as it is.
+pub fn email_client(provider: EmailProvider, base_url: String) -> EmailClient {
+    let token = match provider {
+        EmailProvider::Ses => "AKIDEXAMPLE:secret",
+        _ => "server-token",
+    };
+    let sender = SubscriberEmail::parse("sender@example.com".into()).unwrap();
+    let transport = HttpApiTransport::new(
+        base_url,
+        provider,
+        Some(Secret::new(token.into())),
+        std::time::Duration::from_secs(10),
+    );
+    EmailClient::new(EmailBackend::Http(transport), sender)
+}
+
+pub fn recipient(email: &str, name: &str) -> BatchRecipient {
+    BatchRecipient {
+        email: SubscriberEmail::parse(email.into()).unwrap(),
+        substitutions: BTreeMap::from([("name".to_string(), name.to_string())]),
+    }
+}
+
+pub async fn send(email_client: &EmailClient) -> Result<(), EmailError> {
+    let recipient = SubscriberEmail::parse("recipient@example.com".into()).unwrap();
+    email_client
+        .send_email_with_headers(
+            recipient,
+            "Subject",
+            "<p>Html</p>",
+            "Text",
+            &[("List-Unsubscribe", "<https://x.y/z>")],
+        )
+        .await
+}
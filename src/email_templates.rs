@@ -0,0 +1,352 @@
+use std::path::Path;
+
+pub const CONFIRMATION_VARIABLES: &[&str] = &["name", "confirmation_link"];
+pub const NEWSLETTER_VARIABLES: &[&str] = &["name", "unsubscribe_link"];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Text(String),
+    Variable(String),
+}
+
+// A body with `{{variable}}` placeholders. Only the variables it was parsed
+// against are accepted, so typos are caught before anything is sent; `\{{`
+// stands for a literal `{{`.
+#[derive(Debug, Clone)]
+pub struct Template {
+    segments: Vec<Segment>,
+}
+
+impl Template {
+    pub fn parse(source: &str, allowed: &[&str]) -> Result<Self, TemplateError> {
+        let mut segments = Vec::new();
+        let mut rest = source;
+        while let Some(start) = rest.find("{{") {
+            if let Some(text) = rest[..start].strip_suffix('\\') {
+                segments.push(Segment::Text(format!("{}{{{{", text)));
+                rest = &rest[start + 2..];
+                continue;
+            }
+            if start > 0 {
+                segments.push(Segment::Text(rest[..start].to_owned()));
+            }
+            let after = &rest[start + 2..];
+            let end = after.find("}}").ok_or(TemplateError::UnclosedPlaceholder)?;
+            let name = after[..end].trim();
+            if !allowed.contains(&name) {
+                return Err(TemplateError::UnknownVariable(name.to_owned()));
+            }
+            segments.push(Segment::Variable(name.to_owned()));
+            rest = &after[end + 2..];
+        }
+        if !rest.is_empty() {
+            segments.push(Segment::Text(rest.to_owned()));
+        }
+        Ok(Self { segments })
+    }
+
+    pub fn uses(&self, variable: &str) -> bool {
+        self.segments
+            .iter()
+            .any(|s| matches!(s, Segment::Variable(name) if name == variable))
+    }
+
+    // Values are HTML-escaped when rendering into an HTML body; missing
+    // values render as an empty string.
+    pub fn render(&self, variables: &[(&str, &str)], escape: bool) -> String {
+        let mut output = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Text(text) => output.push_str(text),
+                Segment::Variable(name) => {
+                    let value = variables
+                        .iter()
+                        .find(|(key, _)| key == name)
+                        .map_or("", |(_, value)| value);
+                    if escape {
+                        output.push_str(&htmlescape::encode_minimal(value));
+                    } else {
+                        output.push_str(value);
+                    }
+                }
+            }
+        }
+        output
+    }
+}
+
+// Issues are validated when they are saved; anything stored before
+// templating existed is sent as it is. An empty text part is generated from
+// the HTML.
+pub fn render_newsletter(
+    html_content: &str,
+    text_content: &str,
+    variables: &[(&str, &str)],
+) -> (String, String) {
+    let render =
+        |content: &str, escape| match Template::parse(content, NEWSLETTER_VARIABLES) {
+            Ok(template) => template.render(variables, escape),
+            Err(_) => content.to_owned(),
+        };
+    let html = render(html_content, true);
+    let text = match text_content.trim().is_empty() {
+        true => html_to_text(&html),
+        false => render(text_content, false),
+    };
+    (html, text)
+}
+
+pub struct EmailTemplate {
+    subject: Template,
+    html: Template,
+    // Generated from the rendered HTML when there is no `body.txt`.
+    text: Option<Template>,
+}
+
+pub struct RenderedEmail {
+    pub subject: String,
+    pub html: String,
+    pub text: String,
+}
+
+impl EmailTemplate {
+    pub fn parse(
+        subject: &str,
+        html: &str,
+        text: Option<&str>,
+        allowed: &[&str],
+    ) -> Result<Self, TemplateError> {
+        Ok(Self {
+            subject: Template::parse(subject.trim(), allowed)?,
+            html: Template::parse(html, allowed)?,
+            text: text.map(|t| Template::parse(t, allowed)).transpose()?,
+        })
+    }
+
+    pub fn render(&self, variables: &[(&str, &str)]) -> RenderedEmail {
+        let html = self.html.render(variables, true);
+        let text = match &self.text {
+            Some(text) => text.render(variables, false),
+            None => html_to_text(&html),
+        };
+        RenderedEmail {
+            subject: self.subject.render(variables, false),
+            html,
+            text,
+        }
+    }
+
+    // Each template lives in its own directory, with `subject.txt`,
+    // `body.html` and an optional `body.txt`.
+    fn load(directory: &Path, allowed: &[&str]) -> Result<Self, TemplateError> {
+        let read = |file: &str| {
+            std::fs::read_to_string(directory.join(file)).map_err(|e| {
+                TemplateError::Io(format!("{}: {}", directory.join(file).display(), e))
+            })
+        };
+        let text = match directory.join("body.txt").exists() {
+            true => Some(read("body.txt")?),
+            false => None,
+        };
+        Self::parse(
+            &read("subject.txt")?,
+            &read("body.html")?,
+            text.as_deref(),
+            allowed,
+        )
+    }
+}
+
+pub struct EmailTemplates {
+    pub confirmation: EmailTemplate,
+}
+
+impl EmailTemplates {
+    pub fn load(directory: impl AsRef<Path>) -> Result<Self, TemplateError> {
+        let directory = directory.as_ref();
+        let confirmation =
+            EmailTemplate::load(&directory.join("confirmation"), CONFIRMATION_VARIABLES)?;
+        if !confirmation.html.uses("confirmation_link") {
+            return Err(TemplateError::MissingVariable(
+                "confirmation_link".to_owned(),
+            ));
+        }
+        Ok(Self { confirmation })
+    }
+}
+
+// A plain-text rendition good enough for mail clients that do not show
+// HTML: tags are dropped, block elements become line breaks and links keep
+// their target next to the label.
+pub fn html_to_text(html: &str) -> String {
+    let mut text = String::new();
+    let mut link: Option<String> = None;
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        push_collapsed(&mut text, &rest[..start]);
+        let Some(end) = rest[start..].find('>') else {
+            break;
+        };
+        let tag = &rest[start + 1..start + end];
+        let name = tag
+            .trim_start_matches('/')
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        match name.as_str() {
+            "br" => text.push('\n'),
+            "p" | "div" | "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "ul" | "ol"
+            | "table" | "tr" => text.push_str("\n\n"),
+            "li" if !tag.starts_with('/') => text.push_str("\n- "),
+            "a" if tag.starts_with('/') => {
+                if let Some(href) = link.take() {
+                    text.push_str(&format!(" ({})", href));
+                }
+            }
+            "a" => link = attribute(tag, "href").map(str::to_owned),
+            "head" | "style" | "script" if !tag.starts_with('/') => {
+                let closing = format!("</{}", name);
+                if let Some(skip) = rest.to_ascii_lowercase().find(&closing) {
+                    rest = &rest[skip..];
+                    continue;
+                }
+            }
+            _ => {}
+        }
+        rest = &rest[start + end + 1..];
+    }
+    push_collapsed(&mut text, rest);
+    let text = htmlescape::decode_html(&text).unwrap_or(text);
+    let mut lines = Vec::new();
+    for line in text.lines() {
+        let line = line.trim().to_owned();
+        // Keep at most one blank line between paragraphs.
+        if !line.is_empty() || lines.last().is_some_and(|l: &String| !l.is_empty()) {
+            lines.push(line);
+        }
+    }
+    while lines.last().is_some_and(|l| l.is_empty()) {
+        lines.pop();
+    }
+    lines.join("\n")
+}
+
+// Whitespace in HTML source is not significant, line breaks come from tags.
+fn push_collapsed(text: &mut String, source: &str) {
+    for c in source.chars() {
+        if !c.is_whitespace() {
+            text.push(c);
+        } else if !text.ends_with([' ', '\n']) {
+            text.push(' ');
+        }
+    }
+}
+
+fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let start = tag.find(&format!("{}=\"", name))? + name.len() + 2;
+    let end = tag[start..].find('"')?;
+    Some(&tag[start..start + end])
+}
+
+#[derive(Debug)]
+pub enum TemplateError {
+    Io(String),
+    UnclosedPlaceholder,
+    UnknownVariable(String),
+    MissingVariable(String),
+}
+
+impl std::error::Error for TemplateError {}
+
+impl std::fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TemplateError::Io(e) => write!(f, "Failed to read an email template: {}", e),
+            TemplateError::UnclosedPlaceholder => {
+                write!(f, "A `{{{{` placeholder is never closed")
+            }
+            TemplateError::UnknownVariable(name) => {
+                write!(f, "`{{{{{}}}}}` is not a known template variable", name)
+            }
+            TemplateError::MissingVariable(name) => {
+                write!(f, "The template must use `{{{{{}}}}}`", name)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        html_to_text, EmailTemplate, EmailTemplates, Template, TemplateError,
+        NEWSLETTER_VARIABLES,
+    };
+    use claim::{assert_err, assert_ok};
+
+    #[test]
+    fn variables_are_html_escaped_in_html_bodies_only() {
+        let template = EmailTemplate::parse(
+            "Hi {{name}}",
+            "<p>Hi {{ name }}</p>",
+            None,
+            NEWSLETTER_VARIABLES,
+        )
+        .unwrap();
+
+        let email = template.render(&[("name", "<Tom & Jerry>")]);
+
+        assert_eq!(email.subject, "Hi <Tom & Jerry>");
+        assert_eq!(email.html, "<p>Hi &lt;Tom &amp; Jerry&gt;</p>");
+        assert_eq!(email.text, "Hi <Tom & Jerry>");
+    }
+
+    #[test]
+    fn unknown_variables_are_rejected() {
+        let error = assert_err!(Template::parse("Hi {{nmae}}", NEWSLETTER_VARIABLES));
+        assert!(matches!(error, TemplateError::UnknownVariable(name) if name == "nmae"));
+    }
+
+    #[test]
+    fn escaped_braces_are_rendered_literally() {
+        let template =
+            Template::parse(r"{{name}} wrote \{{ x }} and \\{{y}}", NEWSLETTER_VARIABLES)
+                .unwrap();
+
+        assert_eq!(
+            template.render(&[("name", "Tom")], false),
+            r"Tom wrote {{ x }} and \{{y}}"
+        );
+    }
+
+    #[test]
+    fn unclosed_placeholders_are_rejected() {
+        let error = assert_err!(Template::parse("Hi {{name", NEWSLETTER_VARIABLES));
+        assert!(matches!(error, TemplateError::UnclosedPlaceholder));
+    }
+
+    #[test]
+    fn an_explicit_text_body_replaces_the_generated_one() {
+        let template =
+            EmailTemplate::parse("Hi", "<p>Hi</p>", Some("Hi {{name}}"), &["name"])
+                .unwrap();
+
+        assert_eq!(template.render(&[("name", "Tom")]).text, "Hi Tom");
+    }
+
+    #[test]
+    fn html_to_text_keeps_paragraphs_and_link_targets() {
+        let html = "<html><head><title>x</title></head><h1>News</h1>\n<p>Read\n  <a href=\"https://x.y/?a=1&amp;b=2\">\
+            this</a>.<br/>Bye</p><ul><li>One</li><li>Two</li></ul>";
+
+        assert_eq!(
+            html_to_text(html),
+            "News\n\nRead this (https://x.y/?a=1&b=2).\nBye\n\n- One\n- Two"
+        );
+    }
+
+    #[test]
+    fn the_bundled_templates_are_valid() {
+        assert_ok!(EmailTemplates::load("templates"));
+    }
+}
@@ -0,0 +1,43 @@
+#[derive(Debug)]
+pub struct IdempotencyKey(String);
+
+impl IdempotencyKey {
+    pub fn parse(s: String) -> Result<IdempotencyKey, String> {
+        if s.is_empty() {
+            return Err("The idempotency key cannot be empty.".into());
+        }
+        let max_length = 50;
+        if s.len() >= max_length {
+            return Err(format!(
+                "The idempotency key must be shorter than {} characters.",
+                max_length
+            ));
+        }
+        Ok(Self(s))
+    }
+}
+
+impl AsRef<str> for IdempotencyKey {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use claim::{assert_err, assert_ok};
+
+    #[test]
+    fn empty_string_is_rejected() {
+        assert_err!(IdempotencyKey::parse("".to_string()));
+    }
+    #[test]
+    fn a_key_of_50_characters_is_rejected() {
+        assert_err!(IdempotencyKey::parse("a".repeat(50)));
+    }
+    #[test]
+    fn a_key_of_49_characters_is_parsed_successfully() {
+        assert_ok!(IdempotencyKey::parse("a".repeat(49)));
+    }
+}
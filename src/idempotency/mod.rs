@@ -0,0 +1,7 @@
+mod key;
+mod persistence;
+
+pub use key::IdempotencyKey;
+pub use persistence::{
+    release_key, save_response, try_processing, IdempotencyError, NextAction,
+};
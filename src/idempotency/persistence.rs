@@ -0,0 +1,217 @@
+use super::IdempotencyKey;
+use crate::database::{Db, DbPool};
+use actix_web::body::to_bytes;
+use actix_web::http::StatusCode;
+use actix_web::HttpResponse;
+use chrono::Utc;
+use sqlx::Transaction;
+use std::time::Duration;
+use tokio::time::Instant;
+
+pub enum NextAction {
+    StartProcessing(Box<Transaction<'static, Db>>),
+    ReturnSavedResponse(HttpResponse),
+}
+
+// How long a request waits for another one with the same key to finish
+// before giving up with a conflict.
+const MAX_WAIT: Duration = Duration::from_secs(5);
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// The placeholder row is committed straight away, so no lock is held while
+// the request is processed; a concurrent request with the same key polls
+// until the saved response shows up. The work itself runs in the returned
+// transaction, which `save_response` commits along with the response.
+#[tracing::instrument(skip(pool))]
+pub async fn try_processing(
+    pool: &DbPool,
+    idempotency_key: &IdempotencyKey,
+    user_id: i64,
+) -> Result<NextAction, IdempotencyError> {
+    let idempotency_key = idempotency_key.as_ref();
+    let deadline = Instant::now() + MAX_WAIT;
+    loop {
+        match get_saved_response(pool, idempotency_key, user_id).await? {
+            SavedResponse::Complete(response) => {
+                return Ok(NextAction::ReturnSavedResponse(response));
+            }
+            // A failed request releases its key, so we can take it over.
+            SavedResponse::Missing => {
+                if insert_placeholder(pool, idempotency_key, user_id).await? {
+                    let transaction = pool.begin().await?;
+                    return Ok(NextAction::StartProcessing(Box::new(transaction)));
+                }
+            }
+            SavedResponse::InProgress => {}
+        }
+        if Instant::now() >= deadline {
+            return Err(IdempotencyError::InProgress);
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+async fn insert_placeholder(
+    pool: &DbPool,
+    idempotency_key: &str,
+    user_id: i64,
+) -> Result<bool, IdempotencyError> {
+    let now = Utc::now();
+    let n_inserted_rows = sqlx::query!(
+        r#"
+            INSERT INTO idempotency (user_id, idempotency_key, created_at)
+            VALUES ($1, $2, $3)
+            ON CONFLICT DO NOTHING
+        "#,
+        user_id,
+        idempotency_key,
+        now
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+    Ok(n_inserted_rows > 0)
+}
+
+// Deletes the placeholder of a request that failed, so that retrying it
+// with the same key processes it again instead of waiting for a response
+// that will never be saved.
+#[tracing::instrument(skip(pool))]
+pub async fn release_key(
+    pool: &DbPool,
+    idempotency_key: &IdempotencyKey,
+    user_id: i64,
+) -> Result<(), IdempotencyError> {
+    let idempotency_key = idempotency_key.as_ref();
+    sqlx::query!(
+        r#"
+            DELETE FROM idempotency
+            WHERE user_id = $1 AND idempotency_key = $2
+                AND response_status_code IS NULL
+        "#,
+        user_id,
+        idempotency_key
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+enum SavedResponse {
+    Missing,
+    InProgress,
+    Complete(HttpResponse),
+}
+
+async fn get_saved_response(
+    pool: &DbPool,
+    idempotency_key: &str,
+    user_id: i64,
+) -> Result<SavedResponse, IdempotencyError> {
+    let saved_response = sqlx::query!(
+        r#"
+            SELECT response_status_code, response_headers, response_body
+            FROM idempotency
+            WHERE user_id = $1 AND idempotency_key = $2
+        "#,
+        user_id,
+        idempotency_key
+    )
+    .fetch_optional(pool)
+    .await?;
+    let Some(r) = saved_response else {
+        return Ok(SavedResponse::Missing);
+    };
+    let (Some(status_code), Some(headers), Some(body)) =
+        (r.response_status_code, r.response_headers, r.response_body)
+    else {
+        return Ok(SavedResponse::InProgress);
+    };
+    let status_code = u16::try_from(status_code)
+        .ok()
+        .and_then(|code| StatusCode::from_u16(code).ok())
+        .ok_or_else(|| {
+            IdempotencyError::UnexpectedError("Invalid saved status code".into())
+        })?;
+    let headers: Vec<(String, Vec<u8>)> = serde_json::from_str(&headers)
+        .map_err(|e| IdempotencyError::UnexpectedError(e.to_string()))?;
+    let mut response = HttpResponse::build(status_code);
+    for (name, value) in headers {
+        response.append_header((name, value));
+    }
+    Ok(SavedResponse::Complete(response.body(body)))
+}
+
+#[tracing::instrument(skip(transaction, http_response))]
+pub async fn save_response(
+    mut transaction: Transaction<'static, Db>,
+    idempotency_key: &IdempotencyKey,
+    user_id: i64,
+    http_response: HttpResponse,
+) -> Result<HttpResponse, IdempotencyError> {
+    let (response_head, body) = http_response.into_parts();
+    let body = to_bytes(body)
+        .await
+        .map_err(|e| IdempotencyError::UnexpectedError(e.to_string()))?;
+    let status_code = i32::from(response_head.status().as_u16());
+    let headers: Vec<(String, Vec<u8>)> = response_head
+        .headers()
+        .iter()
+        .map(|(name, value)| (name.as_str().to_owned(), value.as_bytes().to_owned()))
+        .collect();
+    let headers = serde_json::to_string(&headers)
+        .map_err(|e| IdempotencyError::UnexpectedError(e.to_string()))?;
+    let idempotency_key = idempotency_key.as_ref();
+    let body_bytes = body.as_ref();
+    sqlx::query!(
+        r#"
+            UPDATE idempotency
+            SET response_status_code = $1, response_headers = $2, response_body = $3
+            WHERE user_id = $4 AND idempotency_key = $5
+        "#,
+        status_code,
+        headers,
+        body_bytes,
+        user_id,
+        idempotency_key
+    )
+    .execute(transaction.as_mut())
+    .await?;
+    transaction.commit().await?;
+
+    let http_response = response_head.set_body(body).map_into_boxed_body();
+    Ok(http_response)
+}
+
+#[derive(Debug)]
+pub enum IdempotencyError {
+    DatabaseError(sqlx::Error),
+    // Another request with the same key did not finish in time.
+    InProgress,
+    UnexpectedError(String),
+}
+
+impl std::error::Error for IdempotencyError {}
+
+impl From<sqlx::Error> for IdempotencyError {
+    fn from(e: sqlx::Error) -> Self {
+        Self::DatabaseError(e)
+    }
+}
+
+impl std::fmt::Display for IdempotencyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IdempotencyError::DatabaseError(e) => {
+                write!(f, "Failed to access idempotency records: {}", e)
+            }
+            IdempotencyError::InProgress => write!(
+                f,
+                "A request with the same idempotency key is still being processed"
+            ),
+            IdempotencyError::UnexpectedError(e) => {
+                write!(f, "Failed to replay a saved response: {}", e)
+            }
+        }
+    }
+}
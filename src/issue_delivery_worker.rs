@@ -0,0 +1,433 @@
+use crate::database::DbPool;
+use crate::domain::SubscriberEmail;
+use crate::email_client::{EmailClient, EmailError};
+use crate::email_templates::render_newsletter;
+use chrono::Utc;
+use std::time::Duration;
+
+pub enum ExecutionOutcome {
+    TaskCompleted,
+    EmptyQueue,
+}
+
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    pub fn delay_for(&self, n_retries: u32) -> Duration {
+        let factor = 2u32.saturating_pow(n_retries);
+        self.base_delay
+            .checked_mul(factor)
+            .map_or(self.max_delay, |delay| delay.min(self.max_delay))
+    }
+}
+
+pub async fn run_worker_until_stopped(
+    pool: DbPool,
+    email_client: EmailClient,
+    retry_policy: RetryPolicy,
+    base_url: String,
+) {
+    loop {
+        if let Err(e) = release_scheduled_issues(&pool).await {
+            tracing::error!(error.cause_chain = ?e, "Failed to release scheduled issues");
+        }
+        loop {
+            match try_execute_task(&pool, &email_client, &retry_policy, &base_url).await {
+                Ok(ExecutionOutcome::EmptyQueue) => break,
+                Ok(ExecutionOutcome::TaskCompleted) => {}
+                Err(_) => {
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+            }
+        }
+        if let Err(e) = mark_delivered_issues(&pool).await {
+            tracing::error!(error.cause_chain = ?e, "Failed to mark delivered issues");
+        }
+        tokio::time::sleep(Duration::from_secs(10)).await;
+    }
+}
+
+// How long a claimed task stays hidden from other workers. It has to outlast
+// a send, including any wait for the rate limiter.
+const CLAIM_DURATION: Duration = Duration::from_secs(10 * 60);
+
+// No transaction is held while the email is sent: the task is claimed first,
+// then deleted or rescheduled once the provider has answered.
+#[tracing::instrument(
+    skip_all,
+    fields(newsletter_issue_id=tracing::field::Empty, subscriber_email=tracing::field::Empty),
+    err
+)]
+pub async fn try_execute_task(
+    pool: &DbPool,
+    email_client: &EmailClient,
+    retry_policy: &RetryPolicy,
+    base_url: &str,
+) -> Result<ExecutionOutcome, DeliveryError> {
+    let Some(task) = claim_task(pool).await? else {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    };
+    tracing::Span::current()
+        .record(
+            "newsletter_issue_id",
+            tracing::field::display(task.newsletter_issue_id),
+        )
+        .record(
+            "subscriber_email",
+            tracing::field::display(&task.subscriber_email),
+        );
+    match SubscriberEmail::parse(task.subscriber_email.clone()) {
+        Ok(email) => {
+            let Some(recipient) = get_recipient(pool, &task.subscriber_email).await?
+            else {
+                tracing::info!("Skipping a subscriber who is no longer confirmed");
+                delete_task(pool, &task).await?;
+                return Ok(ExecutionOutcome::TaskCompleted);
+            };
+            let unsubscribe_link = format!(
+                "{}/subscriptions/unsubscribe?unsubscribe_token={}",
+                base_url, recipient.unsubscribe_token
+            );
+            let issue = get_issue(pool, task.newsletter_issue_id).await?;
+            let variables = [
+                ("name", recipient.name.as_str()),
+                ("unsubscribe_link", unsubscribe_link.as_str()),
+            ];
+            let (html_content, text_content) =
+                render_newsletter(&issue.html_content, &issue.text_content, &variables);
+            let outcome = email_client
+                .send_email_with_headers(
+                    email,
+                    &issue.title,
+                    &html_content,
+                    &text_content,
+                    &[
+                        ("List-Unsubscribe", &format!("<{}>", unsubscribe_link)),
+                        ("List-Unsubscribe-Post", "List-Unsubscribe=One-Click"),
+                    ],
+                )
+                .await;
+            if let Err(e) = outcome {
+                handle_failed_delivery(pool, task, retry_policy, &e).await?;
+                return Err(e.into());
+            }
+        }
+        Err(error) => {
+            tracing::warn!(
+                error.cause_chain = ?error,
+                "Skipping a confirmed subscriber. \
+                Their stored contact details are invalid",
+            );
+        }
+    }
+    delete_task(pool, &task).await?;
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+// Queues every scheduled issue whose `send_at` has passed, using the
+// subscribers confirmed at that point rather than when it was written.
+#[tracing::instrument(skip_all)]
+pub async fn release_scheduled_issues(pool: &DbPool) -> Result<(), sqlx::Error> {
+    let now = Utc::now();
+    let mut transaction = pool.begin().await?;
+    sqlx::query!(
+        r#"
+            INSERT INTO issue_delivery_queue (newsletter_issue_id, subscriber_email)
+            SELECT i.id, s.email
+            FROM newsletter_issues i, subscriptions s
+            WHERE i.status = 'scheduled' AND i.send_at <= $1
+            AND s.status = 'confirmed'
+        "#,
+        now
+    )
+    .execute(transaction.as_mut())
+    .await?;
+    sqlx::query!(
+        r#"
+            UPDATE newsletter_issues SET status = 'sending'
+            WHERE status = 'scheduled' AND send_at <= $1
+        "#,
+        now
+    )
+    .execute(transaction.as_mut())
+    .await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+// An issue is sent once nothing is left in the queue for it; dead letters
+// requeued later move it back to `sending`.
+#[tracing::instrument(skip_all)]
+pub async fn mark_delivered_issues(pool: &DbPool) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+            UPDATE newsletter_issues SET status = 'sent'
+            WHERE status = 'sending'
+            AND id NOT IN (SELECT newsletter_issue_id FROM issue_delivery_queue)
+        "#
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+struct DeliveryTask {
+    newsletter_issue_id: i64,
+    subscriber_email: String,
+    n_retries: i64,
+}
+
+// Claiming pushes `execute_after` past the claim duration, so a worker that
+// dies mid-send leaves the task to be picked up again once it runs out.
+#[cfg(feature = "sqlite")]
+#[tracing::instrument(skip_all)]
+async fn claim_task(pool: &DbPool) -> Result<Option<DeliveryTask>, sqlx::Error> {
+    let now = Utc::now();
+    let claimed_until = now
+        + chrono::Duration::from_std(CLAIM_DURATION)
+            .expect("Claim duration is out of range.");
+    let task = sqlx::query_as!(
+        DeliveryTask,
+        r#"
+            UPDATE issue_delivery_queue SET execute_after = $2
+            WHERE (newsletter_issue_id, subscriber_email) = (
+                SELECT newsletter_issue_id, subscriber_email FROM issue_delivery_queue
+                WHERE execute_after IS NULL OR execute_after <= $1
+                LIMIT 1
+            )
+            RETURNING newsletter_issue_id, subscriber_email, n_retries
+        "#,
+        now,
+        claimed_until
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(task)
+}
+
+// Workers skip rows another one is claiming instead of queueing behind it.
+#[cfg(feature = "postgres")]
+#[tracing::instrument(skip_all)]
+async fn claim_task(pool: &DbPool) -> Result<Option<DeliveryTask>, sqlx::Error> {
+    let now = Utc::now();
+    let claimed_until = now
+        + chrono::Duration::from_std(CLAIM_DURATION)
+            .expect("Claim duration is out of range.");
+    let task = sqlx::query_as!(
+        DeliveryTask,
+        r#"
+            UPDATE issue_delivery_queue SET execute_after = $2
+            WHERE (newsletter_issue_id, subscriber_email) = (
+                SELECT newsletter_issue_id, subscriber_email FROM issue_delivery_queue
+                WHERE execute_after IS NULL OR execute_after <= $1
+                LIMIT 1
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING newsletter_issue_id, subscriber_email, n_retries
+        "#,
+        now,
+        claimed_until
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(task)
+}
+
+#[tracing::instrument(skip_all)]
+async fn delete_task(pool: &DbPool, task: &DeliveryTask) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+            DELETE FROM issue_delivery_queue
+            WHERE newsletter_issue_id = $1 AND subscriber_email = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn handle_failed_delivery(
+    pool: &DbPool,
+    task: DeliveryTask,
+    retry_policy: &RetryPolicy,
+    error: &EmailError,
+) -> Result<(), sqlx::Error> {
+    let now = Utc::now();
+    let n_retries = task.n_retries + 1;
+    if !error.is_retryable() || n_retries > i64::from(retry_policy.max_retries) {
+        tracing::error!(
+            error.cause_chain = ?error,
+            "Giving up on a delivery after {} retries",
+            n_retries
+        );
+        let last_error = error.to_string();
+        let mut transaction = pool.begin().await?;
+        sqlx::query!(
+            r#"
+                INSERT INTO issue_delivery_dead_letters
+                (newsletter_issue_id, subscriber_email, n_retries, last_error, failed_at)
+                VALUES ($1, $2, $3, $4, $5)
+                ON CONFLICT (newsletter_issue_id, subscriber_email) DO UPDATE
+                SET n_retries = excluded.n_retries, last_error = excluded.last_error,
+                failed_at = excluded.failed_at
+            "#,
+            task.newsletter_issue_id,
+            task.subscriber_email,
+            n_retries,
+            last_error,
+            now
+        )
+        .execute(transaction.as_mut())
+        .await?;
+        sqlx::query!(
+            r#"
+                DELETE FROM issue_delivery_queue
+                WHERE newsletter_issue_id = $1 AND subscriber_email = $2
+            "#,
+            task.newsletter_issue_id,
+            task.subscriber_email
+        )
+        .execute(transaction.as_mut())
+        .await?;
+        transaction.commit().await?;
+    } else {
+        let delay =
+            retry_policy.delay_for(u32::try_from(task.n_retries).unwrap_or(u32::MAX));
+        let delay = error.retry_after().map_or(delay, |after| after.max(delay));
+        let execute_after = now
+            + chrono::Duration::from_std(delay).expect("Retry delay is out of range.");
+        sqlx::query!(
+            r#"
+                UPDATE issue_delivery_queue
+                SET n_retries = $3, execute_after = $4
+                WHERE newsletter_issue_id = $1 AND subscriber_email = $2
+            "#,
+            task.newsletter_issue_id,
+            task.subscriber_email,
+            n_retries,
+            execute_after
+        )
+        .execute(pool)
+        .await?;
+    }
+    Ok(())
+}
+
+struct Recipient {
+    name: String,
+    unsubscribe_token: String,
+}
+
+// Subscribers who unsubscribed after the issue was queued have no
+// confirmed row left, so their pending deliveries are dropped here.
+#[tracing::instrument(skip_all)]
+async fn get_recipient(
+    pool: &DbPool,
+    subscriber_email: &str,
+) -> Result<Option<Recipient>, sqlx::Error> {
+    let recipient = sqlx::query_as!(
+        Recipient,
+        r#"
+            SELECT s.name, t.unsubscribe_token
+            FROM unsubscribe_tokens t
+            JOIN subscriptions s ON s.id = t.subscriber_id
+            WHERE s.email = $1 AND s.status = 'confirmed'
+        "#,
+        subscriber_email
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(recipient)
+}
+
+struct NewsletterIssue {
+    title: String,
+    text_content: String,
+    html_content: String,
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_issue(pool: &DbPool, issue_id: i64) -> Result<NewsletterIssue, sqlx::Error> {
+    let issue = sqlx::query_as!(
+        NewsletterIssue,
+        r#"
+            SELECT title, text_content, html_content
+            FROM newsletter_issues
+            WHERE id = $1
+        "#,
+        issue_id
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(issue)
+}
+
+#[derive(Debug)]
+pub enum DeliveryError {
+    DatabaseError(sqlx::Error),
+    SendEmailError(EmailError),
+}
+
+impl std::error::Error for DeliveryError {}
+
+impl From<sqlx::Error> for DeliveryError {
+    fn from(e: sqlx::Error) -> Self {
+        Self::DatabaseError(e)
+    }
+}
+
+impl From<EmailError> for DeliveryError {
+    fn from(e: EmailError) -> Self {
+        Self::SendEmailError(e)
+    }
+}
+
+impl std::fmt::Display for DeliveryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeliveryError::DatabaseError(e) => {
+                write!(f, "Failed to access the delivery queue: {}", e)
+            }
+            DeliveryError::SendEmailError(e) => {
+                write!(f, "Failed to deliver a newsletter issue: {}", e)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RetryPolicy;
+    use std::time::Duration;
+
+    fn retry_policy() -> RetryPolicy {
+        RetryPolicy {
+            max_retries: 5,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+        }
+    }
+
+    #[test]
+    fn the_delay_doubles_after_each_retry() {
+        let policy = retry_policy();
+        assert_eq!(policy.delay_for(0), Duration::from_secs(1));
+        assert_eq!(policy.delay_for(1), Duration::from_secs(2));
+        assert_eq!(policy.delay_for(4), Duration::from_secs(16));
+    }
+
+    #[test]
+    fn the_delay_is_capped_at_max_delay() {
+        let policy = retry_policy();
+        assert_eq!(policy.delay_for(6), Duration::from_secs(60));
+        assert_eq!(policy.delay_for(100), Duration::from_secs(60));
+    }
+}
@@ -1,7 +1,15 @@
+pub mod authentication;
 pub mod configuration;
-
+pub mod database;
 pub mod domain;
 pub mod email_client;
+pub mod email_templates;
+pub mod idempotency;
+pub mod issue_delivery_worker;
+pub mod markdown;
 pub mod routes;
+pub mod session_state;
+pub mod session_store;
 pub mod startup;
 pub mod telemetry;
+pub mod utils;
@@ -7,7 +7,20 @@ async fn main() -> std::io::Result<()> {
     let subscriber = get_subscriber("zero2prod".into());
     init_subscriber(subscriber);
     let configuration = get_configuration().expect("Failed to read configuration.");
-    let (server, _) = build(&configuration).await?;
-    server.await?;
+    let (server, _, worker) = build(&configuration).await?;
+    let worker = tokio::spawn(worker);
+    // The worker never returns on its own, so it ending means it panicked;
+    // stop rather than keep serving requests whose emails would never go out.
+    tokio::select! {
+        outcome = server => outcome?,
+        outcome = worker => {
+            let error = match outcome {
+                Ok(()) => "The delivery worker stopped".to_string(),
+                Err(e) => format!("The delivery worker failed: {}", e),
+            };
+            tracing::error!("{}", error);
+            return Err(std::io::Error::other(error));
+        }
+    }
     Ok(())
 }
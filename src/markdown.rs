@@ -0,0 +1,232 @@
+use crate::email_templates::NEWSLETTER_VARIABLES;
+use pulldown_cmark::{CowStr, Event, Options, Parser, Tag, TagEnd};
+
+// Email clients ignore `<style>` blocks more often than not, so every
+// element carries its own styling.
+const INLINE_STYLES: &[(&str, &str)] = &[
+    ("h1", "margin:0 0 16px;font-size:24px;line-height:32px;"),
+    ("h2", "margin:24px 0 12px;font-size:20px;line-height:28px;"),
+    ("h3", "margin:20px 0 8px;font-size:16px;line-height:24px;"),
+    ("p", "margin:0 0 16px;font-size:16px;line-height:24px;"),
+    ("a", "color:#1a73e8;text-decoration:underline;"),
+    (
+        "blockquote",
+        "margin:0 0 16px;padding:0 16px;border-left:4px solid #dddddd;color:#555555;",
+    ),
+    (
+        "pre",
+        "margin:0 0 16px;padding:12px;background-color:#f6f8fa;border-radius:4px;\
+        overflow-x:auto;",
+    ),
+    (
+        "code",
+        "font-family:Menlo,Consolas,monospace;font-size:14px;\
+        background-color:#f6f8fa;",
+    ),
+    ("img", "max-width:100%;height:auto;border:0;"),
+    ("ul", "margin:0 0 16px;padding-left:24px;"),
+    ("ol", "margin:0 0 16px;padding-left:24px;"),
+    ("hr", "border:0;border-top:1px solid #dddddd;margin:24px 0;"),
+];
+
+pub struct RenderedMarkdown {
+    pub html: String,
+    pub text: String,
+}
+
+pub fn render(markdown: &str) -> RenderedMarkdown {
+    RenderedMarkdown {
+        html: render_html(markdown),
+        text: render_text(markdown),
+    }
+}
+
+fn options() -> Options {
+    Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TABLES
+}
+
+// Code is shown as written, so any `{{` in it is escaped rather than read
+// as a template placeholder.
+fn events(markdown: &str) -> impl Iterator<Item = Event<'_>> {
+    let mut in_code_block = false;
+    Parser::new_ext(markdown, options()).map(move |event| match event {
+        Event::Start(Tag::CodeBlock(_)) => {
+            in_code_block = true;
+            event
+        }
+        Event::End(TagEnd::CodeBlock) => {
+            in_code_block = false;
+            event
+        }
+        Event::Text(text) if in_code_block => Event::Text(escape_placeholders(text)),
+        Event::Code(code) => Event::Code(escape_placeholders(code)),
+        event => event,
+    })
+}
+
+fn escape_placeholders(code: CowStr<'_>) -> CowStr<'_> {
+    match code.contains("{{") {
+        true => code.replace("{{", "\\{{").into(),
+        false => code,
+    }
+}
+
+// Raw HTML is allowed in Markdown, so the output goes through the sanitizer
+// before any styling is added.
+fn render_html(markdown: &str) -> String {
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(&mut html, events(markdown));
+    let html = ammonia::Builder::default()
+        .add_tag_attributes("img", &["width", "height"])
+        .clean(&html)
+        .to_string();
+    // Keep `{{unsubscribe_link}}` and friends usable as link targets.
+    let html = NEWSLETTER_VARIABLES.iter().fold(html, |html, name| {
+        html.replace(
+            &format!("href=\"%7B%7B{}%7D%7D\"", name),
+            &format!("href=\"{{{{{}}}}}\"", name),
+        )
+    });
+    INLINE_STYLES.iter().fold(html, |html, (tag, style)| {
+        html.replace(
+            &format!("<{}>", tag),
+            &format!("<{} style=\"{}\">", tag, style),
+        )
+        .replace(
+            &format!("<{} ", tag),
+            &format!("<{} style=\"{}\" ", tag, style),
+        )
+    })
+}
+
+fn render_text(markdown: &str) -> String {
+    let mut text = String::new();
+    let mut links = Vec::new();
+    let mut list_numbers = Vec::new();
+    let mut in_code_block = false;
+    for event in events(markdown) {
+        match event {
+            Event::Start(Tag::List(first)) => list_numbers.push(first),
+            Event::End(TagEnd::List(_)) => {
+                list_numbers.pop();
+                if list_numbers.is_empty() {
+                    text.push('\n');
+                }
+            }
+            Event::Start(Tag::Item) => {
+                text.push_str(&"  ".repeat(list_numbers.len().saturating_sub(1)));
+                match list_numbers.last_mut() {
+                    Some(Some(number)) => {
+                        text.push_str(&format!("{}. ", number));
+                        *number += 1;
+                    }
+                    _ => text.push_str("- "),
+                }
+            }
+            Event::End(TagEnd::Item) if !text.ends_with('\n') => text.push('\n'),
+            Event::Start(Tag::CodeBlock(_)) => {
+                in_code_block = true;
+                text.push_str("```\n");
+            }
+            Event::End(TagEnd::CodeBlock) => {
+                in_code_block = false;
+                text.push_str("```\n\n");
+            }
+            Event::Start(Tag::Link { dest_url, .. })
+            | Event::Start(Tag::Image { dest_url, .. }) => links.push(dest_url),
+            Event::End(TagEnd::Link) | Event::End(TagEnd::Image) => {
+                if let Some(url) = links.pop() {
+                    text.push_str(&format!(" ({})", url));
+                }
+            }
+            Event::Start(Tag::BlockQuote(_)) => text.push_str("> "),
+            Event::End(TagEnd::Paragraph)
+            | Event::End(TagEnd::Heading(_))
+            | Event::End(TagEnd::Table)
+                if list_numbers.is_empty() =>
+            {
+                text.push_str("\n\n")
+            }
+            Event::End(TagEnd::TableRow) | Event::End(TagEnd::TableHead) => {
+                text.push('\n')
+            }
+            Event::End(TagEnd::TableCell) => text.push('\t'),
+            Event::Text(content) => text.push_str(&content),
+            Event::Code(code) => text.push_str(&format!("`{}`", code)),
+            Event::SoftBreak if !in_code_block => text.push(' '),
+            Event::HardBreak => text.push('\n'),
+            Event::Rule => text.push_str("----\n\n"),
+            _ => {}
+        }
+    }
+    text.trim_end().to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::render;
+    use crate::email_templates::{Template, NEWSLETTER_VARIABLES};
+
+    #[test]
+    fn html_is_sanitized_and_styled_inline() {
+        let html = render("# Title\n\nHello <script>alert(1)</script>**world**").html;
+
+        assert!(html.starts_with("<h1 style=\"margin:0 0 16px;"));
+        assert!(html.contains("<strong>world</strong>"));
+        assert!(!html.contains("script"));
+    }
+
+    #[test]
+    fn code_blocks_and_images_are_kept() {
+        let markdown = "```rust\nfn main() {}\n```\n\n![A cat](https://x.y/cat.png)";
+
+        let html = render(markdown).html;
+
+        assert!(html.contains("<pre style="));
+        assert!(html.contains("fn main() {}"));
+        assert!(html.contains("src=\"https://x.y/cat.png\""));
+        assert!(html.contains("alt=\"A cat\""));
+    }
+
+    #[test]
+    fn template_variables_survive_in_link_targets() {
+        let html = render("[Unsubscribe]({{unsubscribe_link}})").html;
+
+        assert!(html.contains("href=\"{{unsubscribe_link}}\""));
+    }
+
+    #[test]
+    fn only_known_variables_are_restored_in_link_targets() {
+        let html =
+            render("[Search](https://x.y/?q=%7B%7Bname%7D%7D) [Bad]({{nmae}})").html;
+
+        assert!(html.contains("href=\"https://x.y/?q=%7B%7Bname%7D%7D\""));
+        assert!(html.contains("href=\"%7B%7Bnmae%7D%7D\""));
+    }
+
+    #[test]
+    fn braces_in_code_are_not_template_placeholders() {
+        let markdown = "Hi {{name}}, use `{{ x }}` or\n\n```\n{{#each}}\n```";
+        let rendered = render(markdown);
+
+        for content in [&rendered.html, &rendered.text] {
+            let template = Template::parse(content, NEWSLETTER_VARIABLES).unwrap();
+            let output = template.render(&[("name", "Tom")], false);
+            assert!(output.contains("Hi Tom"));
+            assert!(output.contains("{{ x }}"));
+            assert!(output.contains("{{#each}}"));
+        }
+    }
+
+    #[test]
+    fn the_text_part_stays_readable() {
+        let markdown = "# News\n\nRead [this](https://x.y/z)\nnow.\n\n\
+            1. One\n2. Two\n\n```\nlet x = 1;\n```\n\n> Quoted";
+
+        assert_eq!(
+            render(markdown).text,
+            "News\n\nRead this (https://x.y/z) now.\n\n1. One\n2. Two\n\n\
+            ```\nlet x = 1;\n```\n\n> Quoted"
+        );
+    }
+}
@@ -0,0 +1,151 @@
+use crate::database::{Db, DbPool};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+use chrono::{DateTime, Utc};
+use sqlx::Transaction;
+
+#[derive(serde::Serialize)]
+pub struct DeadLetter {
+    newsletter_issue_id: i64,
+    subscriber_email: String,
+    n_retries: i64,
+    last_error: String,
+    failed_at: DateTime<Utc>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct RequeueData {
+    newsletter_issue_id: i64,
+    subscriber_email: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+pub struct RequeueResponse {
+    requeued: u64,
+}
+
+#[tracing::instrument(name = "List dead-lettered deliveries", skip(pool))]
+pub async fn list_dead_letters(
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, DeadLetterError> {
+    let dead_letters = sqlx::query_as!(
+        DeadLetter,
+        r#"
+            SELECT newsletter_issue_id, subscriber_email, n_retries, last_error,
+            failed_at AS "failed_at: DateTime<Utc>"
+            FROM issue_delivery_dead_letters
+            ORDER BY failed_at
+        "#,
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(HttpResponse::Ok().json(dead_letters))
+}
+
+#[tracing::instrument(
+    name = "Requeue dead-lettered deliveries",
+    skip(body, pool),
+    fields(newsletter_issue_id = %body.newsletter_issue_id)
+)]
+pub async fn requeue_dead_letters(
+    body: web::Json<RequeueData>,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, DeadLetterError> {
+    let mut transaction = pool.begin().await?;
+    let requeued = move_dead_letters_to_queue(
+        &mut transaction,
+        body.newsletter_issue_id,
+        body.subscriber_email.as_deref(),
+    )
+    .await?;
+    transaction.commit().await?;
+    Ok(HttpResponse::Ok().json(RequeueResponse { requeued }))
+}
+
+// Without a subscriber email every dead letter of the issue is requeued.
+#[tracing::instrument(skip(transaction))]
+async fn move_dead_letters_to_queue(
+    transaction: &mut Transaction<'_, Db>,
+    newsletter_issue_id: i64,
+    subscriber_email: Option<&str>,
+) -> Result<u64, sqlx::Error> {
+    let requeued = sqlx::query!(
+        r#"
+            INSERT INTO issue_delivery_queue (newsletter_issue_id, subscriber_email)
+            SELECT newsletter_issue_id, subscriber_email
+            FROM issue_delivery_dead_letters
+            WHERE newsletter_issue_id = $1
+            AND subscriber_email = COALESCE($2, subscriber_email)
+            ON CONFLICT DO NOTHING
+        "#,
+        newsletter_issue_id,
+        subscriber_email
+    )
+    .execute(transaction.as_mut())
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?
+    .rows_affected();
+    sqlx::query!(
+        r#"
+            UPDATE newsletter_issues SET status = 'sending'
+            WHERE id = $1 AND status = 'sent'
+        "#,
+        newsletter_issue_id
+    )
+    .execute(transaction.as_mut())
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    sqlx::query!(
+        r#"
+            DELETE FROM issue_delivery_dead_letters
+            WHERE newsletter_issue_id = $1
+            AND subscriber_email = COALESCE($2, subscriber_email)
+        "#,
+        newsletter_issue_id,
+        subscriber_email
+    )
+    .execute(transaction.as_mut())
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(requeued)
+}
+
+#[derive(Debug)]
+pub enum DeadLetterError {
+    DatabaseError(sqlx::Error),
+}
+
+impl std::error::Error for DeadLetterError {}
+
+impl ResponseError for DeadLetterError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            DeadLetterError::DatabaseError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl From<sqlx::Error> for DeadLetterError {
+    fn from(e: sqlx::Error) -> Self {
+        Self::DatabaseError(e)
+    }
+}
+
+impl std::fmt::Display for DeadLetterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Failed to manage dead-lettered deliveries.")
+    }
+}
@@ -0,0 +1,445 @@
+use crate::database::DbPool;
+use crate::domain::SubscriberEmail;
+use crate::email_client::{EmailClient, EmailError};
+use crate::email_templates::{render_newsletter, TemplateError};
+use crate::routes::{enqueue_delivery_tasks, validate_send_at, Content};
+use actix_web::http::header::{ContentType, CONTENT_SECURITY_POLICY};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+use chrono::{DateTime, Utc};
+
+// Previews and test copies are not sent to a real subscriber.
+const SAMPLE_VARIABLES: &[(&str, &str)] =
+    &[("name", "Ursula Le Guin"), ("unsubscribe_link", "#")];
+
+#[derive(serde::Serialize)]
+pub struct IssueSummary {
+    newsletter_issue_id: i64,
+    title: String,
+    status: String,
+    send_at: Option<DateTime<Utc>>,
+}
+
+#[derive(serde::Serialize)]
+pub struct Issue {
+    newsletter_issue_id: i64,
+    title: String,
+    status: String,
+    text_content: String,
+    html_content: String,
+    send_at: Option<DateTime<Utc>>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct DraftData {
+    title: String,
+    content: Content,
+}
+
+#[derive(serde::Serialize)]
+pub struct DraftResponse {
+    newsletter_issue_id: i64,
+}
+
+#[derive(serde::Deserialize)]
+pub struct PreviewParameters {
+    #[serde(default)]
+    format: PreviewFormat,
+}
+
+#[derive(serde::Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum PreviewFormat {
+    #[default]
+    Html,
+    Text,
+}
+
+#[derive(serde::Deserialize)]
+pub struct TestSendData {
+    email: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct PublishDraftData {
+    send_at: Option<DateTime<Utc>>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct RescheduleData {
+    send_at: DateTime<Utc>,
+}
+
+#[tracing::instrument(name = "List newsletter issues", skip(pool))]
+pub async fn list_issues(pool: web::Data<DbPool>) -> Result<HttpResponse, IssueError> {
+    let issues = sqlx::query_as!(
+        IssueSummary,
+        r#"
+            SELECT id AS newsletter_issue_id, title, status,
+            send_at AS "send_at: DateTime<Utc>"
+            FROM newsletter_issues
+            ORDER BY id DESC
+        "#,
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(HttpResponse::Ok().json(issues))
+}
+
+#[tracing::instrument(name = "Create a draft newsletter issue", skip(body, pool))]
+pub async fn create_draft(
+    body: web::Json<DraftData>,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, IssueError> {
+    let DraftData { title, content } = body.0;
+    let (html_content, text_content) = content.into_parts()?;
+    let now = Utc::now();
+    let newsletter_issue_id = sqlx::query_scalar!(
+        r#"
+            INSERT INTO newsletter_issues
+            (title, text_content, html_content, published_at, status)
+            VALUES ($1, $2, $3, $4, 'draft')
+            RETURNING id
+        "#,
+        title,
+        text_content,
+        html_content,
+        now
+    )
+    .fetch_one(pool.get_ref())
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(HttpResponse::Created().json(DraftResponse {
+        newsletter_issue_id,
+    }))
+}
+
+#[tracing::instrument(name = "Get a newsletter issue", skip(pool))]
+pub async fn get_issue(
+    issue_id: web::Path<i64>,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, IssueError> {
+    let issue = fetch_issue(&pool, *issue_id).await?;
+    Ok(HttpResponse::Ok().json(issue))
+}
+
+#[tracing::instrument(name = "Update a draft newsletter issue", skip(body, pool))]
+pub async fn update_draft(
+    issue_id: web::Path<i64>,
+    body: web::Json<DraftData>,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, IssueError> {
+    let DraftData { title, content } = body.0;
+    let (html_content, text_content) = content.into_parts()?;
+    let issue_id = *issue_id;
+    let result = sqlx::query!(
+        r#"
+            UPDATE newsletter_issues
+            SET title = $1, text_content = $2, html_content = $3
+            WHERE id = $4 AND status = 'draft'
+        "#,
+        title,
+        text_content,
+        html_content,
+        issue_id
+    )
+    .execute(pool.get_ref())
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    ensure_draft_was_changed(&pool, issue_id, result.rows_affected()).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[tracing::instrument(name = "Delete a draft newsletter issue", skip(pool))]
+pub async fn delete_draft(
+    issue_id: web::Path<i64>,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, IssueError> {
+    let issue_id = *issue_id;
+    let result = sqlx::query!(
+        r#"DELETE FROM newsletter_issues WHERE id = $1 AND status = 'draft'"#,
+        issue_id
+    )
+    .execute(pool.get_ref())
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    ensure_draft_was_changed(&pool, issue_id, result.rows_affected()).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[tracing::instrument(name = "Preview a newsletter issue", skip(parameters, pool))]
+pub async fn preview_issue(
+    issue_id: web::Path<i64>,
+    parameters: web::Query<PreviewParameters>,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, IssueError> {
+    let issue = fetch_issue(&pool, *issue_id).await?;
+    let (html_content, text_content) =
+        render_newsletter(&issue.html_content, &issue.text_content, SAMPLE_VARIABLES);
+    let response = match parameters.format {
+        // Issue HTML is not sanitized, so the browser runs it in a sandbox
+        // with no scripts and a unique origin, away from the admin session.
+        PreviewFormat::Html => HttpResponse::Ok()
+            .content_type(ContentType::html())
+            .insert_header((CONTENT_SECURITY_POLICY, "sandbox"))
+            .body(html_content),
+        PreviewFormat::Text => HttpResponse::Ok()
+            .content_type(ContentType::plaintext())
+            .body(text_content),
+    };
+    Ok(response)
+}
+
+// The copy goes straight through the email client: nothing is queued and
+// the subscriber list is left alone.
+#[tracing::instrument(
+    name = "Send a test copy of a newsletter issue",
+    skip(body, pool, email_client)
+)]
+pub async fn test_send_issue(
+    issue_id: web::Path<i64>,
+    body: web::Json<TestSendData>,
+    pool: web::Data<DbPool>,
+    email_client: web::Data<EmailClient>,
+) -> Result<HttpResponse, IssueError> {
+    let recipient =
+        SubscriberEmail::parse(body.0.email).map_err(IssueError::InvalidRecipient)?;
+    let issue = fetch_issue(&pool, *issue_id).await?;
+    let (html_content, text_content) =
+        render_newsletter(&issue.html_content, &issue.text_content, SAMPLE_VARIABLES);
+    email_client
+        .send_email(
+            recipient,
+            &format!("[Test] {}", issue.title),
+            &html_content,
+            &text_content,
+        )
+        .await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[tracing::instrument(name = "Publish a draft newsletter issue", skip(body, pool))]
+pub async fn publish_draft(
+    issue_id: web::Path<i64>,
+    body: web::Json<PublishDraftData>,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, IssueError> {
+    let issue_id = *issue_id;
+    let send_at = body
+        .send_at
+        .map(validate_send_at)
+        .transpose()
+        .map_err(IssueError::InvalidSendAt)?;
+    let published_at = send_at.unwrap_or_else(Utc::now);
+    let status = match send_at {
+        Some(_) => "scheduled",
+        None => "sending",
+    };
+    let mut transaction = pool.begin().await?;
+    let result = sqlx::query!(
+        r#"
+            UPDATE newsletter_issues
+            SET status = $1, send_at = $2, published_at = $3
+            WHERE id = $4 AND status = 'draft'
+        "#,
+        status,
+        send_at,
+        published_at,
+        issue_id
+    )
+    .execute(transaction.as_mut())
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    ensure_draft_was_changed(&pool, issue_id, result.rows_affected()).await?;
+    if send_at.is_none() {
+        enqueue_delivery_tasks(&mut transaction, issue_id).await?;
+    }
+    transaction.commit().await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[tracing::instrument(name = "Reschedule a newsletter issue", skip(body, pool))]
+pub async fn reschedule_issue(
+    issue_id: web::Path<i64>,
+    body: web::Json<RescheduleData>,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, IssueError> {
+    let issue_id = *issue_id;
+    let send_at = validate_send_at(body.send_at).map_err(IssueError::InvalidSendAt)?;
+    let result = sqlx::query!(
+        r#"
+            UPDATE newsletter_issues SET send_at = $1, published_at = $1
+            WHERE id = $2 AND status = 'scheduled'
+        "#,
+        send_at,
+        issue_id
+    )
+    .execute(pool.get_ref())
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    ensure_scheduled_issue_was_changed(&pool, issue_id, result.rows_affected()).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+// Only issues that have not gone out yet can be cancelled; the row is kept
+// for the record.
+#[tracing::instrument(name = "Cancel a scheduled newsletter issue", skip(pool))]
+pub async fn cancel_issue(
+    issue_id: web::Path<i64>,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, IssueError> {
+    let issue_id = *issue_id;
+    let result = sqlx::query!(
+        r#"
+            UPDATE newsletter_issues SET status = 'cancelled'
+            WHERE id = $1 AND status = 'scheduled'
+        "#,
+        issue_id
+    )
+    .execute(pool.get_ref())
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    ensure_scheduled_issue_was_changed(&pool, issue_id, result.rows_affected()).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+async fn fetch_issue(pool: &DbPool, issue_id: i64) -> Result<Issue, IssueError> {
+    sqlx::query_as!(
+        Issue,
+        r#"
+            SELECT id AS newsletter_issue_id, title, status, text_content, html_content,
+            send_at AS "send_at: DateTime<Utc>"
+            FROM newsletter_issues
+            WHERE id = $1
+        "#,
+        issue_id
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?
+    .ok_or(IssueError::NotFound)
+}
+
+// Tells a missing issue apart from one that has already left the draft
+// stage when a draft-only statement changed nothing.
+async fn ensure_draft_was_changed(
+    pool: &DbPool,
+    issue_id: i64,
+    rows_affected: u64,
+) -> Result<(), IssueError> {
+    if rows_affected > 0 {
+        return Ok(());
+    }
+    let issue = fetch_issue(pool, issue_id).await?;
+    Err(IssueError::NotADraft(issue.status))
+}
+
+// Tells a missing issue apart from one that is no longer scheduled when a
+// statement restricted to scheduled issues changed nothing.
+async fn ensure_scheduled_issue_was_changed(
+    pool: &DbPool,
+    issue_id: i64,
+    rows_affected: u64,
+) -> Result<(), IssueError> {
+    if rows_affected > 0 {
+        return Ok(());
+    }
+    let issue = fetch_issue(pool, issue_id).await?;
+    Err(IssueError::NotScheduled(issue.status))
+}
+
+#[derive(Debug)]
+pub enum IssueError {
+    NotFound,
+    NotADraft(String),
+    NotScheduled(String),
+    InvalidContent(TemplateError),
+    InvalidRecipient(String),
+    InvalidSendAt(String),
+    SendEmailError(EmailError),
+    DatabaseError(sqlx::Error),
+}
+
+impl std::error::Error for IssueError {}
+
+impl ResponseError for IssueError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            IssueError::NotFound => StatusCode::NOT_FOUND,
+            IssueError::NotADraft(_) | IssueError::NotScheduled(_) => {
+                StatusCode::CONFLICT
+            }
+            IssueError::InvalidContent(_)
+            | IssueError::InvalidRecipient(_)
+            | IssueError::InvalidSendAt(_) => StatusCode::BAD_REQUEST,
+            IssueError::SendEmailError(_) => StatusCode::BAD_GATEWAY,
+            IssueError::DatabaseError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl From<sqlx::Error> for IssueError {
+    fn from(e: sqlx::Error) -> Self {
+        Self::DatabaseError(e)
+    }
+}
+
+impl From<TemplateError> for IssueError {
+    fn from(e: TemplateError) -> Self {
+        Self::InvalidContent(e)
+    }
+}
+
+impl From<EmailError> for IssueError {
+    fn from(e: EmailError) -> Self {
+        Self::SendEmailError(e)
+    }
+}
+
+impl std::fmt::Display for IssueError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IssueError::NotFound => write!(f, "There is no issue with that id."),
+            IssueError::NotADraft(status) => {
+                write!(f, "Only drafts can be changed, this issue is {}.", status)
+            }
+            IssueError::NotScheduled(status) => write!(
+                f,
+                "Only scheduled issues can be changed, this issue is {}.",
+                status
+            ),
+            IssueError::InvalidContent(e) => write!(f, "{}", e),
+            IssueError::InvalidRecipient(e) => write!(f, "{}", e),
+            IssueError::InvalidSendAt(e) => write!(f, "{}", e),
+            IssueError::SendEmailError(e) => write!(f, "{}", e),
+            IssueError::DatabaseError(_) => {
+                write!(f, "Failed to manage newsletter issues.")
+            }
+        }
+    }
+}
@@ -0,0 +1,11 @@
+mod dashboard;
+mod dead_letters;
+mod issues;
+mod logout;
+mod scheduled_issues;
+
+pub use dashboard::*;
+pub use dead_letters::*;
+pub use issues::*;
+pub use logout::*;
+pub use scheduled_issues::*;
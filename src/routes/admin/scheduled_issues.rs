@@ -0,0 +1,33 @@
+use crate::database::DbPool;
+use crate::routes::IssueError;
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Utc};
+
+#[derive(serde::Serialize)]
+pub struct ScheduledIssue {
+    newsletter_issue_id: i64,
+    title: String,
+    send_at: DateTime<Utc>,
+}
+
+#[tracing::instrument(name = "List scheduled newsletter issues", skip(pool))]
+pub async fn list_scheduled_issues(
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, IssueError> {
+    let issues = sqlx::query_as!(
+        ScheduledIssue,
+        r#"
+            SELECT id AS newsletter_issue_id, title, send_at AS "send_at!: DateTime<Utc>"
+            FROM newsletter_issues
+            WHERE status = 'scheduled'
+            ORDER BY send_at
+        "#,
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(HttpResponse::Ok().json(issues))
+}
@@ -0,0 +1,74 @@
+use crate::authentication::{validate_credentials, AuthError, Credentials};
+use crate::database::DbPool;
+use crate::session_state::TypedSession;
+use crate::utils::see_other;
+use actix_web::error::InternalError;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use secrecy::Secret;
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    username: String,
+    password: Secret<String>,
+}
+
+#[tracing::instrument(
+    skip(form, pool, session),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn login(
+    form: web::Form<FormData>,
+    pool: web::Data<DbPool>,
+    session: TypedSession,
+) -> Result<HttpResponse, InternalError<LoginError>> {
+    let credentials = Credentials {
+        username: form.0.username,
+        password: form.0.password,
+    };
+    tracing::Span::current()
+        .record("username", tracing::field::display(&credentials.username));
+    match validate_credentials(credentials, &pool).await {
+        Ok(user_id) => {
+            tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+            session.renew();
+            session
+                .insert_user_id(user_id)
+                .map_err(|e| login_redirect(LoginError::SessionError(e.to_string())))?;
+            Ok(see_other("/admin/dashboard"))
+        }
+        Err(e) => {
+            let e = match e {
+                AuthError::InvalidCredentials => LoginError::AuthError(e),
+                _ => LoginError::UnexpectedError(e),
+            };
+            Err(login_redirect(e))
+        }
+    }
+}
+
+// Redirect to the login page with an error message.
+fn login_redirect(e: LoginError) -> InternalError<LoginError> {
+    FlashMessage::error(e.to_string()).send();
+    InternalError::from_response(e, see_other("/login"))
+}
+
+#[derive(Debug)]
+pub enum LoginError {
+    AuthError(AuthError),
+    SessionError(String),
+    UnexpectedError(AuthError),
+}
+
+impl std::error::Error for LoginError {}
+
+impl std::fmt::Display for LoginError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoginError::AuthError(_) => write!(f, "Authentication failed"),
+            LoginError::SessionError(_) | LoginError::UnexpectedError(_) => {
+                write!(f, "Something went wrong")
+            }
+        }
+    }
+}
@@ -0,0 +1,287 @@
+use crate::authentication::{basic_authentication, validate_credentials, AuthError};
+use crate::database::{Db, DbPool};
+use crate::email_templates::{Template, TemplateError, NEWSLETTER_VARIABLES};
+use crate::idempotency::{
+    release_key, save_response, try_processing, IdempotencyError, IdempotencyKey,
+    NextAction,
+};
+use crate::markdown;
+use crate::session_state::TypedSession;
+use actix_web::http::header::{HeaderValue, WWW_AUTHENTICATE};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
+use chrono::{DateTime, Utc};
+use sqlx::Transaction;
+
+#[derive(serde::Deserialize)]
+pub struct BodyData {
+    title: String,
+    content: Content,
+    // Issues with a `send_at` in the future are stored and only queued for
+    // delivery once that time has passed.
+    send_at: Option<DateTime<Utc>>,
+}
+
+// Writers either send Markdown, which is rendered to both parts here, or
+// ready-made HTML with an optional plain-text part.
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+pub enum Content {
+    Markdown {
+        markdown: String,
+    },
+    Html {
+        html: String,
+        // Generated from the HTML at delivery time when left empty.
+        #[serde(default)]
+        text: String,
+    },
+}
+
+impl Content {
+    // Returns the validated HTML and plain-text parts.
+    pub fn into_parts(self) -> Result<(String, String), TemplateError> {
+        let (html_content, text_content) = match self {
+            Content::Markdown { markdown } => {
+                let rendered = markdown::render(&markdown);
+                (rendered.html, rendered.text)
+            }
+            Content::Html { html, text } => (html, text),
+        };
+        for content in [&html_content, &text_content] {
+            Template::parse(content, NEWSLETTER_VARIABLES)?;
+        }
+        Ok((html_content, text_content))
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct PublishResponse {
+    newsletter_issue_id: i64,
+    deliveries_queued: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    send_at: Option<DateTime<Utc>>,
+}
+
+#[tracing::instrument(
+    name = "Publish a newsletter issue",
+    skip(body, pool, request, session),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn publish_newsletter(
+    body: web::Bytes,
+    pool: web::Data<DbPool>,
+    request: HttpRequest,
+    session: TypedSession,
+) -> Result<HttpResponse, PublishError> {
+    let user_id = authenticate_publisher(&request, &session, &pool).await?;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+    // Parsed only once the caller is known, so anonymous requests learn
+    // nothing about the expected payload.
+    let BodyData {
+        title,
+        content,
+        send_at,
+    } = serde_json::from_slice(&body)
+        .map_err(|e| PublishError::InvalidBody(e.to_string()))?;
+    let send_at = send_at
+        .map(validate_send_at)
+        .transpose()
+        .map_err(PublishError::InvalidSendAt)?;
+    let (html_content, text_content) = content
+        .into_parts()
+        .map_err(|e| PublishError::InvalidContent(e.to_string()))?;
+    let idempotency_key = idempotency_key(&request)?;
+    let mut transaction = match try_processing(&pool, &idempotency_key, user_id).await? {
+        NextAction::StartProcessing(t) => *t,
+        NextAction::ReturnSavedResponse(saved_response) => return Ok(saved_response),
+    };
+    let result = async {
+        let issue_id = insert_newsletter_issue(
+            &mut transaction,
+            &title,
+            &text_content,
+            &html_content,
+            send_at,
+        )
+        .await?;
+        let deliveries_queued = match send_at {
+            Some(_) => 0,
+            None => enqueue_delivery_tasks(&mut transaction, issue_id).await?,
+        };
+        let response = HttpResponse::Ok().json(PublishResponse {
+            newsletter_issue_id: issue_id,
+            deliveries_queued,
+            send_at,
+        });
+        Ok(save_response(transaction, &idempotency_key, user_id, response).await?)
+    }
+    .await;
+    if result.is_err() {
+        release_key(&pool, &idempotency_key, user_id).await?;
+    }
+    result
+}
+
+// A time in the past would send the issue straight away, which is unlikely
+// to be what the caller meant.
+pub fn validate_send_at(send_at: DateTime<Utc>) -> Result<DateTime<Utc>, String> {
+    if send_at <= Utc::now() {
+        return Err(format!("`send_at` must be in the future, got {}.", send_at));
+    }
+    Ok(send_at)
+}
+
+fn idempotency_key(request: &HttpRequest) -> Result<IdempotencyKey, PublishError> {
+    let header_value = request
+        .headers()
+        .get("Idempotency-Key")
+        .ok_or("The 'Idempotency-Key' header was missing.")
+        .and_then(|value| {
+            value
+                .to_str()
+                .map_err(|_| "The 'Idempotency-Key' header was not a valid UTF8 string.")
+        })
+        .map_err(|e| PublishError::InvalidIdempotencyKey(e.to_string()))?;
+    IdempotencyKey::parse(header_value.to_owned())
+        .map_err(PublishError::InvalidIdempotencyKey)
+}
+
+// Admins logged in through the browser reuse their session, machine
+// clients send 'Basic' credentials instead.
+async fn authenticate_publisher(
+    request: &HttpRequest,
+    session: &TypedSession,
+    pool: &DbPool,
+) -> Result<i64, PublishError> {
+    if let Ok(Some(user_id)) = session.get_user_id() {
+        return Ok(user_id);
+    }
+    let credentials = basic_authentication(request.headers())
+        .map_err(PublishError::InvalidAuthorizationHeader)?;
+    tracing::Span::current()
+        .record("username", tracing::field::display(&credentials.username));
+    validate_credentials(credentials, pool)
+        .await
+        .map_err(PublishError::AuthError)
+}
+
+#[tracing::instrument(name = "Saving newsletter issue details in the database", skip_all)]
+pub async fn insert_newsletter_issue(
+    transaction: &mut Transaction<'_, Db>,
+    title: &str,
+    text_content: &str,
+    html_content: &str,
+    send_at: Option<DateTime<Utc>>,
+) -> Result<i64, sqlx::Error> {
+    let published_at = send_at.unwrap_or_else(Utc::now);
+    let status = match send_at {
+        Some(_) => "scheduled",
+        None => "sending",
+    };
+    let newsletter_issue_id = sqlx::query_scalar!(
+        r#"
+            INSERT INTO newsletter_issues
+            (title, text_content, html_content, published_at, status, send_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING id
+        "#,
+        title,
+        text_content,
+        html_content,
+        published_at,
+        status,
+        send_at
+    )
+    .fetch_one(transaction.as_mut())
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(newsletter_issue_id)
+}
+
+#[tracing::instrument(name = "Enqueue delivery tasks", skip(transaction))]
+pub async fn enqueue_delivery_tasks(
+    transaction: &mut Transaction<'_, Db>,
+    newsletter_issue_id: i64,
+) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+            INSERT INTO issue_delivery_queue (newsletter_issue_id, subscriber_email)
+            SELECT $1, email FROM subscriptions
+            WHERE status = 'confirmed'
+        "#,
+        newsletter_issue_id,
+    )
+    .execute(transaction.as_mut())
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(result.rows_affected())
+}
+
+#[derive(Debug)]
+pub enum PublishError {
+    AuthError(AuthError),
+    InvalidAuthorizationHeader(String),
+    InvalidIdempotencyKey(String),
+    InvalidBody(String),
+    InvalidContent(String),
+    InvalidSendAt(String),
+    IdempotencyError(IdempotencyError),
+    DatabaseError(sqlx::Error),
+}
+
+impl std::error::Error for PublishError {}
+
+impl ResponseError for PublishError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            PublishError::AuthError(AuthError::InvalidCredentials)
+            | PublishError::InvalidAuthorizationHeader(_) => StatusCode::UNAUTHORIZED,
+            PublishError::InvalidIdempotencyKey(_)
+            | PublishError::InvalidBody(_)
+            | PublishError::InvalidContent(_)
+            | PublishError::InvalidSendAt(_) => StatusCode::BAD_REQUEST,
+            PublishError::IdempotencyError(IdempotencyError::InProgress) => {
+                StatusCode::CONFLICT
+            }
+            PublishError::AuthError(_)
+            | PublishError::IdempotencyError(_)
+            | PublishError::DatabaseError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::new(self.status_code());
+        if self.status_code() == StatusCode::UNAUTHORIZED {
+            let header_value = HeaderValue::from_str(r#"Basic realm="publish""#).unwrap();
+            response
+                .headers_mut()
+                .insert(WWW_AUTHENTICATE, header_value);
+        }
+        response
+    }
+}
+
+impl From<sqlx::Error> for PublishError {
+    fn from(e: sqlx::Error) -> Self {
+        Self::DatabaseError(e)
+    }
+}
+
+impl From<IdempotencyError> for PublishError {
+    fn from(e: IdempotencyError) -> Self {
+        Self::IdempotencyError(e)
+    }
+}
+
+impl std::fmt::Display for PublishError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Failed to publish a newsletter issue.")
+    }
+}
@@ -1,9 +1,14 @@
+use crate::database::{Db, DbPool};
 use crate::domain::{NewSubscriber, SubscriberEmail, SubscriberName};
+use crate::email_client::{EmailClient, EmailError};
+use crate::email_templates::EmailTemplates;
+use crate::startup::ApplicationBaseUrl;
 use actix_web::http::StatusCode;
 use actix_web::{web, HttpResponse, ResponseError};
 use chrono::Utc;
-use sqlx::SqlitePool;
-use sqlx::{Sqlite, Transaction};
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use sqlx::Transaction;
 use std::convert::{TryFrom, TryInto};
 
 #[derive(serde::Deserialize)]
@@ -24,7 +29,7 @@ impl TryFrom<FormData> for NewSubscriber {
 
 #[tracing::instrument(
     name = "Adding a new subscriber",
-    skip(form, pool),
+    skip(form, pool, email_client, email_templates, base_url),
     fields(
         subscriber_email = %form.email,
         subscriber_name= %form.name
@@ -32,51 +37,230 @@ impl TryFrom<FormData> for NewSubscriber {
 )]
 pub async fn subscribe(
     form: web::Form<FormData>,
-    pool: web::Data<SqlitePool>,
+    pool: web::Data<DbPool>,
+    email_client: web::Data<EmailClient>,
+    email_templates: web::Data<EmailTemplates>,
+    base_url: web::Data<ApplicationBaseUrl>,
 ) -> Result<HttpResponse, SubscribeError> {
     let new_subscriber = form.0.try_into()?;
     let mut transaction = pool.begin().await?;
-    insert_subscriber(&new_subscriber, &mut transaction).await?;
-    // if insert_subscriber(&new_subscriber, &mut transaction).await.is_err() {
-    //     return HttpResponse::InternalServerError().finish();
-    // }
+    let subscriber_id = match insert_subscriber(&new_subscriber, &mut transaction).await?
+    {
+        Some(subscriber_id) => {
+            let unsubscribe_token = generate_subscription_token();
+            store_unsubscribe_token(&mut transaction, subscriber_id, &unsubscribe_token)
+                .await?;
+            subscriber_id
+        }
+        None => {
+            let existing =
+                get_existing_subscriber(&mut transaction, &new_subscriber.email).await?;
+            // Confirmed subscribers get the same response as everyone else,
+            // so the endpoint does not reveal which addresses are on the list.
+            if existing.status.as_deref() == Some("confirmed") {
+                transaction.commit().await?;
+                return Ok(HttpResponse::Ok().finish());
+            }
+            // Pending and unsubscribed rows go through confirmation again;
+            // the status only changes once the new link is followed.
+            existing.id
+        }
+    };
+    // Only the most recent confirmation link stays valid.
+    delete_subscription_tokens(&mut transaction, subscriber_id).await?;
+    let subscription_token = generate_subscription_token();
+    store_token(&mut transaction, subscriber_id, &subscription_token).await?;
     transaction.commit().await?;
+    send_confirmation_email(
+        &email_client,
+        &email_templates,
+        new_subscriber,
+        &base_url.0,
+        &subscription_token,
+    )
+    .await?;
     Ok(HttpResponse::Ok().finish())
 }
 
+fn generate_subscription_token() -> String {
+    let mut rng = thread_rng();
+    std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(25)
+        .collect()
+}
+
+#[tracing::instrument(
+    name = "Send a confirmation email to a new subscriber",
+    skip(
+        email_client,
+        email_templates,
+        new_subscriber,
+        base_url,
+        subscription_token
+    )
+)]
+pub async fn send_confirmation_email(
+    email_client: &EmailClient,
+    email_templates: &EmailTemplates,
+    new_subscriber: NewSubscriber,
+    base_url: &str,
+    subscription_token: &str,
+) -> Result<(), EmailError> {
+    let confirmation_link = format!(
+        "{}/subscriptions/confirm?subscription_token={}",
+        base_url, subscription_token
+    );
+    let email = email_templates.confirmation.render(&[
+        ("name", new_subscriber.name.as_ref()),
+        ("confirmation_link", &confirmation_link),
+    ]);
+    email_client
+        .send_email(
+            new_subscriber.email,
+            &email.subject,
+            &email.html,
+            &email.text,
+        )
+        .await
+}
+
+#[tracing::instrument(
+    name = "Store subscription token in the database",
+    skip(subscription_token, transaction)
+)]
+pub async fn store_token(
+    transaction: &mut Transaction<'_, Db>,
+    subscriber_id: i64,
+    subscription_token: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+            INSERT INTO subscription_tokens (subscription_token, subscriber_id)
+            VALUES ($1, $2)
+        "#,
+        subscription_token,
+        subscriber_id
+    )
+    .execute(transaction.as_mut())
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(())
+}
+
+#[tracing::instrument(
+    name = "Delete subscription tokens from the database",
+    skip(transaction)
+)]
+pub async fn delete_subscription_tokens(
+    transaction: &mut Transaction<'_, Db>,
+    subscriber_id: i64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"DELETE FROM subscription_tokens WHERE subscriber_id = $1"#,
+        subscriber_id
+    )
+    .execute(transaction.as_mut())
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(())
+}
+
+#[tracing::instrument(
+    name = "Store unsubscribe token in the database",
+    skip(unsubscribe_token, transaction)
+)]
+pub async fn store_unsubscribe_token(
+    transaction: &mut Transaction<'_, Db>,
+    subscriber_id: i64,
+    unsubscribe_token: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+            INSERT INTO unsubscribe_tokens (unsubscribe_token, subscriber_id)
+            VALUES ($1, $2)
+        "#,
+        unsubscribe_token,
+        subscriber_id
+    )
+    .execute(transaction.as_mut())
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(())
+}
+
 #[tracing::instrument(
     name = "Saving new subscriber details in the database",
     skip(new_subscriber, transaction)
 )]
 pub async fn insert_subscriber(
     new_subscriber: &NewSubscriber,
-    transaction: &mut Transaction<'_, Sqlite>,
-) -> Result<(), sqlx::Error> {
+    transaction: &mut Transaction<'_, Db>,
+) -> Result<Option<i64>, sqlx::Error> {
     let now = Utc::now();
     let name = new_subscriber.name.as_ref();
     let email = new_subscriber.email.as_ref();
-    sqlx::query!(
+    let subscriber_id = sqlx::query_scalar!(
         r#"
-            INSERT INTO subscriptions (email, name, subscribed_at, status) 
+            INSERT INTO subscriptions (email, name, subscribed_at, status)
             VALUES ($1, $2, $3, 'pending_confirmation')
+            ON CONFLICT (email) DO NOTHING
+            RETURNING id
         "#,
         email,
         name,
         now
     )
-    .execute(transaction.as_mut())
+    .fetch_optional(transaction.as_mut())
     .await
     .map_err(|e| {
         tracing::error!("Failed to execute query: {:?}", e);
         e
     })?;
-    Ok(())
+    Ok(subscriber_id)
+}
+
+pub struct ExistingSubscriber {
+    id: i64,
+    status: Option<String>,
+}
+
+#[tracing::instrument(
+    name = "Looking up an existing subscriber",
+    skip(subscriber_email, transaction)
+)]
+pub async fn get_existing_subscriber(
+    transaction: &mut Transaction<'_, Db>,
+    subscriber_email: &SubscriberEmail,
+) -> Result<ExistingSubscriber, sqlx::Error> {
+    let email = subscriber_email.as_ref();
+    sqlx::query_as!(
+        ExistingSubscriber,
+        r#"SELECT id, status FROM subscriptions WHERE email = $1"#,
+        email
+    )
+    .fetch_one(transaction.as_mut())
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })
 }
 
 #[derive(Debug)]
 pub enum SubscribeError {
     ValidationError(String),
     DatabaseError(sqlx::Error),
+    SendEmailError(EmailError),
 }
 
 impl std::error::Error for SubscribeError {}
@@ -85,7 +269,9 @@ impl ResponseError for SubscribeError {
     fn status_code(&self) -> StatusCode {
         match self {
             SubscribeError::ValidationError(_) => StatusCode::BAD_REQUEST,
-            SubscribeError::DatabaseError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            SubscribeError::DatabaseError(_) | SubscribeError::SendEmailError(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
         }
     }
 }
@@ -96,6 +282,12 @@ impl From<sqlx::Error> for SubscribeError {
     }
 }
 
+impl From<EmailError> for SubscribeError {
+    fn from(e: EmailError) -> Self {
+        Self::SendEmailError(e)
+    }
+}
+
 impl From<String> for SubscribeError {
     fn from(e: String) -> Self {
         Self::ValidationError(e)
@@ -0,0 +1,92 @@
+use crate::database::DbPool;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+
+#[derive(serde::Deserialize)]
+pub struct Parameters {
+    subscription_token: String,
+}
+
+#[tracing::instrument(name = "Confirm a pending subscriber", skip(parameters, pool))]
+pub async fn confirm(
+    parameters: web::Query<Parameters>,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, ConfirmError> {
+    let subscriber_id =
+        get_subscriber_id_from_token(&pool, &parameters.subscription_token)
+            .await?
+            .ok_or(ConfirmError::UnknownToken)?;
+    confirm_subscriber(&pool, subscriber_id).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[tracing::instrument(name = "Mark subscriber as confirmed", skip(subscriber_id, pool))]
+pub async fn confirm_subscriber(
+    pool: &DbPool,
+    subscriber_id: i64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"UPDATE subscriptions SET status = 'confirmed' WHERE id = $1"#,
+        subscriber_id,
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(())
+}
+
+#[tracing::instrument(
+    name = "Get subscriber_id from token",
+    skip(subscription_token, pool)
+)]
+pub async fn get_subscriber_id_from_token(
+    pool: &DbPool,
+    subscription_token: &str,
+) -> Result<Option<i64>, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+            SELECT subscriber_id FROM subscription_tokens
+            WHERE subscription_token = $1
+        "#,
+        subscription_token,
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(result.map(|r| r.subscriber_id))
+}
+
+#[derive(Debug)]
+pub enum ConfirmError {
+    UnknownToken,
+    DatabaseError(sqlx::Error),
+}
+
+impl std::error::Error for ConfirmError {}
+
+impl ResponseError for ConfirmError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ConfirmError::UnknownToken => StatusCode::UNAUTHORIZED,
+            ConfirmError::DatabaseError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl From<sqlx::Error> for ConfirmError {
+    fn from(e: sqlx::Error) -> Self {
+        Self::DatabaseError(e)
+    }
+}
+
+impl std::fmt::Display for ConfirmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Failed to confirm a pending subscriber.")
+    }
+}
@@ -0,0 +1,136 @@
+use crate::database::{Db, DbPool};
+use crate::routes::delete_subscription_tokens;
+use actix_web::http::header::ContentType;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+use sqlx::Transaction;
+
+#[derive(serde::Deserialize)]
+pub struct UnsubscribeParameters {
+    unsubscribe_token: String,
+}
+
+// Link scanners and prefetching mail clients follow links in emails, so the
+// link only shows a form; submitting it does the unsubscribing.
+#[tracing::instrument(name = "Show the unsubscribe form", skip(parameters, pool))]
+pub async fn unsubscribe_form(
+    parameters: web::Query<UnsubscribeParameters>,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, UnsubscribeError> {
+    get_subscriber_id_from_unsubscribe_token(&pool, &parameters.unsubscribe_token)
+        .await?
+        .ok_or(UnsubscribeError::UnknownToken)?;
+    let unsubscribe_token = htmlescape::encode_attribute(&parameters.unsubscribe_token);
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Unsubscribe</title>
+</head>
+<body>
+    <p>Do you want to stop receiving our newsletter?</p>
+    <form action="/subscriptions/unsubscribe?unsubscribe_token={unsubscribe_token}" method="post">
+        <button type="submit">Unsubscribe</button>
+    </form>
+</body>
+</html>"#,
+        )))
+}
+
+// Handles both the form above and the one-click `List-Unsubscribe-Post`
+// request mail clients send.
+#[tracing::instrument(name = "Unsubscribe a subscriber", skip(parameters, pool))]
+pub async fn unsubscribe(
+    parameters: web::Query<UnsubscribeParameters>,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, UnsubscribeError> {
+    let subscriber_id =
+        get_subscriber_id_from_unsubscribe_token(&pool, &parameters.unsubscribe_token)
+            .await?
+            .ok_or(UnsubscribeError::UnknownToken)?;
+    let mut transaction = pool.begin().await?;
+    mark_subscriber_as_unsubscribed(&mut transaction, subscriber_id).await?;
+    // Confirmation links sent earlier must not subscribe them again.
+    delete_subscription_tokens(&mut transaction, subscriber_id).await?;
+    transaction.commit().await?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body("<p>You have been unsubscribed.</p>"))
+}
+
+#[tracing::instrument(
+    name = "Mark subscriber as unsubscribed",
+    skip(subscriber_id, transaction)
+)]
+pub async fn mark_subscriber_as_unsubscribed(
+    transaction: &mut Transaction<'_, Db>,
+    subscriber_id: i64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"UPDATE subscriptions SET status = 'unsubscribed' WHERE id = $1"#,
+        subscriber_id,
+    )
+    .execute(transaction.as_mut())
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(())
+}
+
+#[tracing::instrument(
+    name = "Get subscriber_id from unsubscribe token",
+    skip(unsubscribe_token, pool)
+)]
+pub async fn get_subscriber_id_from_unsubscribe_token(
+    pool: &DbPool,
+    unsubscribe_token: &str,
+) -> Result<Option<i64>, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+            SELECT subscriber_id FROM unsubscribe_tokens
+            WHERE unsubscribe_token = $1
+        "#,
+        unsubscribe_token,
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(result.map(|r| r.subscriber_id))
+}
+
+#[derive(Debug)]
+pub enum UnsubscribeError {
+    UnknownToken,
+    DatabaseError(sqlx::Error),
+}
+
+impl std::error::Error for UnsubscribeError {}
+
+impl ResponseError for UnsubscribeError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            UnsubscribeError::UnknownToken => StatusCode::UNAUTHORIZED,
+            UnsubscribeError::DatabaseError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl From<sqlx::Error> for UnsubscribeError {
+    fn from(e: sqlx::Error) -> Self {
+        Self::DatabaseError(e)
+    }
+}
+
+impl std::fmt::Display for UnsubscribeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Failed to unsubscribe a subscriber.")
+    }
+}
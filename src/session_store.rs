@@ -0,0 +1,291 @@
+use crate::database::DbPool;
+use actix_session::storage::{
+    generate_session_key, LoadError, SaveError, SessionKey, SessionStore, UpdateError,
+};
+use actix_web::cookie::time::Duration;
+use chrono::Utc;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
+
+type SessionState = HashMap<String, String>;
+
+#[derive(serde::Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionStoreKind {
+    Memory,
+    // Sessions live in the application database, whichever backend it is.
+    #[serde(alias = "sqlite")]
+    Database,
+}
+
+#[derive(Clone)]
+pub enum SessionBackend {
+    Memory(InMemorySessionStore),
+    Database(DatabaseSessionStore),
+}
+
+impl SessionBackend {
+    pub fn new(kind: SessionStoreKind, pool: DbPool) -> Self {
+        match kind {
+            SessionStoreKind::Memory => Self::Memory(InMemorySessionStore::default()),
+            SessionStoreKind::Database => Self::Database(DatabaseSessionStore::new(pool)),
+        }
+    }
+}
+
+impl SessionStore for SessionBackend {
+    async fn load(
+        &self,
+        session_key: &SessionKey,
+    ) -> Result<Option<SessionState>, LoadError> {
+        match self {
+            Self::Memory(store) => store.load(session_key).await,
+            Self::Database(store) => store.load(session_key).await,
+        }
+    }
+
+    async fn save(
+        &self,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, SaveError> {
+        match self {
+            Self::Memory(store) => store.save(session_state, ttl).await,
+            Self::Database(store) => store.save(session_state, ttl).await,
+        }
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        match self {
+            Self::Memory(store) => store.update(session_key, session_state, ttl).await,
+            Self::Database(store) => store.update(session_key, session_state, ttl).await,
+        }
+    }
+
+    async fn update_ttl(
+        &self,
+        session_key: &SessionKey,
+        ttl: &Duration,
+    ) -> Result<(), anyhow::Error> {
+        match self {
+            Self::Memory(store) => store.update_ttl(session_key, ttl).await,
+            Self::Database(store) => store.update_ttl(session_key, ttl).await,
+        }
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> Result<(), anyhow::Error> {
+        match self {
+            Self::Memory(store) => store.delete(session_key).await,
+            Self::Database(store) => store.delete(session_key).await,
+        }
+    }
+}
+
+// Sessions live in the process, so they are lost on restart and are not
+// shared between replicas.
+#[derive(Clone, Default)]
+pub struct InMemorySessionStore {
+    sessions: Arc<RwLock<HashMap<String, (SessionState, Instant)>>>,
+}
+
+impl InMemorySessionStore {
+    fn expires_at(ttl: &Duration) -> Instant {
+        Instant::now() + std::time::Duration::from_secs(ttl.whole_seconds().max(0) as u64)
+    }
+}
+
+impl SessionStore for InMemorySessionStore {
+    async fn load(
+        &self,
+        session_key: &SessionKey,
+    ) -> Result<Option<SessionState>, LoadError> {
+        let sessions = self.sessions.read().unwrap();
+        let state = sessions
+            .get(session_key.as_ref())
+            .filter(|(_, expires_at)| *expires_at > Instant::now())
+            .map(|(state, _)| state.clone());
+        Ok(state)
+    }
+
+    async fn save(
+        &self,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, SaveError> {
+        let session_key = generate_session_key();
+        let mut sessions = self.sessions.write().unwrap();
+        sessions.retain(|_, (_, expires_at)| *expires_at > Instant::now());
+        sessions.insert(
+            session_key.as_ref().to_owned(),
+            (session_state, Self::expires_at(ttl)),
+        );
+        Ok(session_key)
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        if let Some(session) =
+            self.sessions.write().unwrap().get_mut(session_key.as_ref())
+        {
+            *session = (session_state, Self::expires_at(ttl));
+            return Ok(session_key);
+        }
+        self.save(session_state, ttl)
+            .await
+            .map_err(|e| UpdateError::Other(anyhow::Error::new(e)))
+    }
+
+    async fn update_ttl(
+        &self,
+        session_key: &SessionKey,
+        ttl: &Duration,
+    ) -> Result<(), anyhow::Error> {
+        if let Some((_, expires_at)) =
+            self.sessions.write().unwrap().get_mut(session_key.as_ref())
+        {
+            *expires_at = Self::expires_at(ttl);
+        }
+        Ok(())
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> Result<(), anyhow::Error> {
+        self.sessions.write().unwrap().remove(session_key.as_ref());
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+pub struct DatabaseSessionStore {
+    pool: DbPool,
+}
+
+impl DatabaseSessionStore {
+    pub fn new(pool: DbPool) -> Self {
+        Self { pool }
+    }
+
+    fn expires_at(ttl: &Duration) -> chrono::DateTime<Utc> {
+        Utc::now() + chrono::Duration::seconds(ttl.whole_seconds())
+    }
+}
+
+impl SessionStore for DatabaseSessionStore {
+    async fn load(
+        &self,
+        session_key: &SessionKey,
+    ) -> Result<Option<SessionState>, LoadError> {
+        let session_key = session_key.as_ref();
+        let now = Utc::now();
+        let row = sqlx::query!(
+            r#"
+                SELECT session_state FROM sessions
+                WHERE session_key = $1 AND expires_at > $2
+            "#,
+            session_key,
+            now
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| LoadError::Other(anyhow::Error::new(e)))?;
+        row.map(|r| serde_json::from_str(&r.session_state))
+            .transpose()
+            .map_err(|e| LoadError::Deserialization(anyhow::Error::new(e)))
+    }
+
+    async fn save(
+        &self,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, SaveError> {
+        let session_state = serde_json::to_string(&session_state)
+            .map_err(|e| SaveError::Serialization(anyhow::Error::new(e)))?;
+        let session_key = generate_session_key();
+        let key = session_key.as_ref();
+        let now = Utc::now();
+        let expires_at = Self::expires_at(ttl);
+        sqlx::query!("DELETE FROM sessions WHERE expires_at <= $1", now)
+            .execute(&self.pool)
+            .await
+            .map_err(|e| SaveError::Other(anyhow::Error::new(e)))?;
+        sqlx::query!(
+            r#"
+                INSERT INTO sessions (session_key, session_state, expires_at)
+                VALUES ($1, $2, $3)
+            "#,
+            key,
+            session_state,
+            expires_at
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| SaveError::Other(anyhow::Error::new(e)))?;
+        Ok(session_key)
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        let serialized_state = serde_json::to_string(&session_state)
+            .map_err(|e| UpdateError::Serialization(anyhow::Error::new(e)))?;
+        let key = session_key.as_ref();
+        let expires_at = Self::expires_at(ttl);
+        let updated = sqlx::query!(
+            r#"
+                UPDATE sessions SET session_state = $1, expires_at = $2
+                WHERE session_key = $3
+            "#,
+            serialized_state,
+            expires_at,
+            key
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| UpdateError::Other(anyhow::Error::new(e)))?
+        .rows_affected();
+        if updated == 0 {
+            return self
+                .save(session_state, ttl)
+                .await
+                .map_err(|e| UpdateError::Other(anyhow::Error::new(e)));
+        }
+        Ok(session_key)
+    }
+
+    async fn update_ttl(
+        &self,
+        session_key: &SessionKey,
+        ttl: &Duration,
+    ) -> Result<(), anyhow::Error> {
+        let key = session_key.as_ref();
+        let expires_at = Self::expires_at(ttl);
+        sqlx::query!(
+            "UPDATE sessions SET expires_at = $1 WHERE session_key = $2",
+            expires_at,
+            key
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> Result<(), anyhow::Error> {
+        let key = session_key.as_ref();
+        sqlx::query!("DELETE FROM sessions WHERE session_key = $1", key)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+}
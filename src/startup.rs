@@ -1,31 +1,62 @@
+use crate::authentication::{create_user_if_missing, reject_anonymous_users};
 use crate::configuration::DatabaseSettings;
 use crate::configuration::Settings;
+use crate::database::{run_migrations, DbPool, DbPoolOptions};
 use crate::email_client::EmailClient;
+use crate::email_templates::EmailTemplates;
+use crate::issue_delivery_worker::run_worker_until_stopped;
 use crate::routes;
+use crate::session_store::SessionBackend;
+use actix_session::SessionMiddleware;
+use actix_web::cookie::Key;
 use actix_web::dev::Server;
+use actix_web::middleware::from_fn;
 use actix_web::web::Data;
 use actix_web::{web, App, HttpServer};
-use sqlx::sqlite::SqlitePoolOptions;
-use sqlx::SqlitePool;
+use actix_web_flash_messages::storage::CookieMessageStore;
+use actix_web_flash_messages::FlashMessagesFramework;
+use secrecy::{ExposeSecret, Secret};
+use std::future::Future;
 use std::net::TcpListener;
 use tracing_actix_web::TracingLogger;
 
-pub async fn get_connection_pool(configuration: &DatabaseSettings) -> SqlitePool {
-    SqlitePoolOptions::new()
-        .acquire_timeout(std::time::Duration::from_secs(2))
-        .connect(&configuration.connection_string())
+pub async fn get_connection_pool(configuration: &DatabaseSettings) -> DbPool {
+    let connect_options = configuration
+        .connect_options()
+        .expect("Invalid database configuration.");
+    DbPoolOptions::new()
+        .max_connections(configuration.max_connections)
+        .min_connections(configuration.min_connections)
+        .idle_timeout(configuration.idle_timeout())
+        .acquire_timeout(configuration.acquire_timeout())
+        .connect_with(connect_options)
         .await
-        .expect("Failed to connect to sqlite.")
+        .expect("Failed to connect to the database.")
 }
 
-pub async fn build(configuration: &Settings) -> Result<(Server, u16), std::io::Error> {
+// The delivery worker shares the server's pool and email client, so both stay
+// within the same connection and rate limits. It only runs once it is polled.
+pub async fn build(
+    configuration: &Settings,
+) -> Result<(Server, u16, impl Future<Output = ()>), std::io::Error> {
     let connection_pool = get_connection_pool(&configuration.database).await;
-    let sender_email = configuration
+    if configuration.database.migrate_on_startup {
+        run_migrations(&connection_pool)
+            .await
+            .map_err(std::io::Error::other)?;
+    }
+    if let Some(admin) = &configuration.admin {
+        create_user_if_missing(admin.credentials(), &connection_pool)
+            .await
+            .expect("Failed to seed the admin user.");
+    }
+    let email_client = configuration
         .email_client
-        .sender()
-        .expect("Invalid sender email address.");
-    let email_client =
-        EmailClient::new(configuration.email_client.base_url.clone(), sender_email);
+        .client()
+        .expect("Invalid email client configuration.");
+    let email_templates =
+        EmailTemplates::load(&configuration.application.templates_directory)
+            .expect("Invalid email templates.");
 
     let address = format!(
         "{}:{}",
@@ -33,24 +64,112 @@ pub async fn build(configuration: &Settings) -> Result<(Server, u16), std::io::E
     );
     let listener = TcpListener::bind(address)?;
     let port = listener.local_addr().unwrap().port();
-    let server = run(listener, connection_pool, email_client)?;
-    Ok((server, port))
+    let session_store = SessionBackend::new(
+        configuration.application.session_store,
+        connection_pool.clone(),
+    );
+    let worker = run_worker_until_stopped(
+        connection_pool.clone(),
+        email_client.clone(),
+        configuration.email_client.retry_policy(),
+        configuration.application.base_url.clone(),
+    );
+    let server = run(
+        listener,
+        connection_pool,
+        email_client,
+        email_templates,
+        configuration.application.base_url.clone(),
+        configuration.application.hmac_secret.clone(),
+        session_store,
+    )?;
+    Ok((server, port, worker))
 }
 
+pub struct ApplicationBaseUrl(pub String);
+
 pub fn run(
     listener: TcpListener,
-    db_pool: SqlitePool,
+    db_pool: DbPool,
     email_client: EmailClient,
+    email_templates: EmailTemplates,
+    base_url: String,
+    hmac_secret: Secret<String>,
+    session_store: SessionBackend,
 ) -> Result<Server, std::io::Error> {
     let db_pool = Data::new(db_pool);
     let email_client = Data::new(email_client);
+    let email_templates = Data::new(email_templates);
+    let base_url = Data::new(ApplicationBaseUrl(base_url));
+    let secret_key = Key::from(hmac_secret.expose_secret().as_bytes());
+    let message_store = CookieMessageStore::builder(secret_key.clone()).build();
+    let message_framework = FlashMessagesFramework::builder(message_store).build();
     let server = HttpServer::new(move || {
         App::new()
+            .wrap(message_framework.clone())
+            .wrap(SessionMiddleware::new(
+                session_store.clone(),
+                secret_key.clone(),
+            ))
             .wrap(TracingLogger::default())
             .route("/health_check", web::get().to(routes::health_check))
+            .route("/login", web::get().to(routes::login_form))
+            .route("/login", web::post().to(routes::login))
             .route("/subscriptions", web::post().to(routes::subscribe))
+            .route("/subscriptions/confirm", web::get().to(routes::confirm))
+            .route(
+                "/subscriptions/unsubscribe",
+                web::get().to(routes::unsubscribe_form),
+            )
+            .route(
+                "/subscriptions/unsubscribe",
+                web::post().to(routes::unsubscribe),
+            )
+            .route("/newsletters", web::post().to(routes::publish_newsletter))
+            .service(
+                web::scope("/admin")
+                    .wrap(from_fn(reject_anonymous_users))
+                    .route("/dashboard", web::get().to(routes::admin_dashboard))
+                    .route("/logout", web::post().to(routes::log_out))
+                    .route("/dead_letters", web::get().to(routes::list_dead_letters))
+                    .route(
+                        "/dead_letters/requeue",
+                        web::post().to(routes::requeue_dead_letters),
+                    )
+                    .route("/issues", web::get().to(routes::list_issues))
+                    .route("/issues", web::post().to(routes::create_draft))
+                    .route("/issues/{issue_id}", web::get().to(routes::get_issue))
+                    .route("/issues/{issue_id}", web::put().to(routes::update_draft))
+                    .route("/issues/{issue_id}", web::delete().to(routes::delete_draft))
+                    .route(
+                        "/issues/{issue_id}/preview",
+                        web::get().to(routes::preview_issue),
+                    )
+                    .route(
+                        "/issues/{issue_id}/test_send",
+                        web::post().to(routes::test_send_issue),
+                    )
+                    .route(
+                        "/issues/{issue_id}/publish",
+                        web::post().to(routes::publish_draft),
+                    )
+                    .route(
+                        "/issues/{issue_id}/reschedule",
+                        web::post().to(routes::reschedule_issue),
+                    )
+                    .route(
+                        "/issues/{issue_id}/cancel",
+                        web::post().to(routes::cancel_issue),
+                    )
+                    .route(
+                        "/scheduled_issues",
+                        web::get().to(routes::list_scheduled_issues),
+                    ),
+            )
             .app_data(db_pool.clone())
             .app_data(email_client.clone())
+            .app_data(email_templates.clone())
+            .app_data(base_url.clone())
     })
     .listen(listener)?
     .run();
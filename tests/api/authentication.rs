@@ -0,0 +1,56 @@
+use super::helpers::spawn_app;
+use claim::assert_ok;
+use secrecy::Secret;
+use zero2prod::authentication::{validate_credentials, AuthError, Credentials};
+use zero2prod::configuration::{get_configuration, AdminSettings};
+use zero2prod::startup::build;
+
+#[actix_rt::test]
+async fn the_admin_user_is_seeded_from_configuration() {
+    let app = spawn_app().await;
+    let credentials = Credentials {
+        username: app.test_user.username.clone(),
+        password: Secret::new(app.test_user.password.clone()),
+    };
+    assert_ok!(validate_credentials(credentials, &app.db_pool).await);
+}
+
+#[actix_rt::test]
+async fn an_invalid_password_is_rejected() {
+    let app = spawn_app().await;
+    let credentials = Credentials {
+        username: app.test_user.username.clone(),
+        password: Secret::new("definitely-not-the-password".into()),
+    };
+    let outcome = validate_credentials(credentials, &app.db_pool).await;
+    assert!(matches!(outcome, Err(AuthError::InvalidCredentials)));
+}
+
+#[actix_rt::test]
+async fn an_unknown_username_is_rejected() {
+    let app = spawn_app().await;
+    let credentials = Credentials {
+        username: "unknown".into(),
+        password: Secret::new(app.test_user.password.clone()),
+    };
+    let outcome = validate_credentials(credentials, &app.db_pool).await;
+    assert!(matches!(outcome, Err(AuthError::InvalidCredentials)));
+}
+
+#[actix_rt::test]
+async fn seeding_does_not_overwrite_an_existing_password() {
+    let app = spawn_app().await;
+    let mut configuration = get_configuration().unwrap();
+    configuration.database.url = app.database.url.clone();
+    configuration.admin = Some(AdminSettings {
+        username: app.test_user.username.clone(),
+        password: Secret::new("a-new-password".into()),
+    });
+    let (_server, _, _) = build(&configuration).await.unwrap();
+
+    let credentials = Credentials {
+        username: app.test_user.username.clone(),
+        password: Secret::new(app.test_user.password.clone()),
+    };
+    assert_ok!(validate_credentials(credentials, &app.db_pool).await);
+}
@@ -0,0 +1,179 @@
+use super::helpers::{assert_is_redirect_to, spawn_app};
+use super::newsletters::{create_confirmed_subscriber, newsletter_request_body};
+use std::time::Duration;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::issue_delivery_worker::{try_execute_task, RetryPolicy};
+
+#[actix_rt::test]
+async fn deliveries_that_exhaust_their_retries_are_dead_lettered() {
+    let app = spawn_app().await;
+    let email = create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    let retry_policy = RetryPolicy {
+        max_retries: 1,
+        base_delay: Duration::ZERO,
+        max_delay: Duration::ZERO,
+    };
+
+    app.post_newsletters(&newsletter_request_body())
+        .await
+        .error_for_status()
+        .unwrap();
+    for _ in 0..2 {
+        let outcome = try_execute_task(
+            &app.db_pool,
+            &app.email_client,
+            &retry_policy,
+            &app.address,
+        )
+        .await;
+        assert!(outcome.is_err());
+    }
+
+    app.login().await;
+    let response = app
+        .api_client
+        .get(format!("{}/admin/dead_letters", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(200, response.status().as_u16());
+    let dead_letters: serde_json::Value = response.json().await.unwrap();
+    let dead_letters = dead_letters.as_array().unwrap();
+    assert_eq!(dead_letters.len(), 1);
+    assert_eq!(dead_letters[0]["subscriber_email"], email.as_str());
+    assert_eq!(dead_letters[0]["n_retries"], 2);
+    let queued =
+        sqlx::query!(r#"SELECT COUNT(*) AS "count!: i64" FROM issue_delivery_queue"#)
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(queued.count, 0);
+}
+
+#[actix_rt::test]
+async fn permanently_rejected_deliveries_are_dead_lettered_without_retrying() {
+    let app = spawn_app().await;
+    let email = create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(422).set_body_json(serde_json::json!({
+            "ErrorCode": 406,
+            "Message": "The address is inactive."
+        })))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_newsletters(&newsletter_request_body())
+        .await
+        .error_for_status()
+        .unwrap();
+    let outcome = try_execute_task(
+        &app.db_pool,
+        &app.email_client,
+        &app.retry_policy,
+        &app.address,
+    )
+    .await;
+
+    assert!(outcome.is_err());
+    let dead_letter = sqlx::query!(
+        "SELECT subscriber_email, n_retries, last_error FROM issue_delivery_dead_letters"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(dead_letter.subscriber_email, email);
+    assert_eq!(dead_letter.n_retries, 1);
+    assert!(dead_letter.last_error.contains("The address is inactive."));
+}
+
+#[actix_rt::test]
+async fn requeued_dead_letters_are_delivered_again() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let retry_policy = RetryPolicy {
+        max_retries: 0,
+        base_delay: Duration::ZERO,
+        max_delay: Duration::ZERO,
+    };
+    let response = app.post_newsletters(&newsletter_request_body()).await;
+    assert_eq!(200, response.status().as_u16());
+    {
+        let _mock_guard = Mock::given(path("/email"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount_as_scoped(&app.email_server)
+            .await;
+        let outcome = try_execute_task(
+            &app.db_pool,
+            &app.email_client,
+            &retry_policy,
+            &app.address,
+        )
+        .await;
+        assert!(outcome.is_err());
+    }
+    let issue =
+        sqlx::query!("SELECT newsletter_issue_id FROM issue_delivery_dead_letters")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+
+    app.login().await;
+    let response = app
+        .api_client
+        .post(format!("{}/admin/dead_letters/requeue", app.address))
+        .json(&serde_json::json!({"newsletter_issue_id": issue.newsletter_issue_id}))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(200, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["requeued"], 1);
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+    let dead_letters = sqlx::query!(
+        r#"SELECT COUNT(*) AS "count!: i64" FROM issue_delivery_dead_letters"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(dead_letters.count, 0);
+}
+
+#[actix_rt::test]
+async fn you_must_be_logged_in_to_manage_dead_letters() {
+    let app = spawn_app().await;
+
+    let response = app
+        .api_client
+        .get(format!("{}/admin/dead_letters", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_is_redirect_to(&response, "/login");
+
+    let response = app
+        .api_client
+        .post(format!("{}/admin/dead_letters/requeue", app.address))
+        .json(&serde_json::json!({"newsletter_issue_id": 1}))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_is_redirect_to(&response, "/login");
+}
@@ -1,7 +1,16 @@
 use once_cell::sync::Lazy;
-use sqlx::SqlitePool;
-use std::time::{SystemTime, UNIX_EPOCH};
-use zero2prod::configuration::get_configuration;
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use secrecy::Secret;
+use wiremock::MockServer;
+use zero2prod::configuration::{get_configuration, AdminSettings, DatabaseSettings};
+use zero2prod::database::{DbPool, MIGRATOR};
+use zero2prod::email_client::EmailClient;
+use zero2prod::issue_delivery_worker::{
+    mark_delivered_issues, release_scheduled_issues, try_execute_task, ExecutionOutcome,
+    RetryPolicy,
+};
+use zero2prod::session_store::SessionStoreKind;
 use zero2prod::startup::{build, get_connection_pool};
 use zero2prod::telemetry::{get_subscriber, init_subscriber};
 
@@ -10,30 +19,313 @@ static TRACING: Lazy<()> = Lazy::new(|| {
     init_subscriber(subscriber);
 });
 
-pub fn unix_timestamp() -> u128 {
-    let start = SystemTime::now();
-    start
-        .duration_since(UNIX_EPOCH)
-        .expect("Time went backwards")
-        .as_micros()
+pub async fn spawn_app() -> TestApp {
+    spawn_app_with_session_store(SessionStoreKind::Database).await
 }
 
-pub async fn spawn_app() -> TestApp {
+pub async fn spawn_app_with_session_store(session_store: SessionStoreKind) -> TestApp {
     Lazy::force(&TRACING);
-    let configuration = get_configuration().expect("Failed to read configuration.");
-    let (server, port) = build(&configuration)
+    let email_server = MockServer::start().await;
+    let test_user = TestUser::generate();
+    let mut configuration = {
+        let mut c = get_configuration().expect("Failed to read configuration.");
+        c.email_client.base_url = email_server.uri();
+        c.application.session_store = session_store;
+        c.admin = Some(AdminSettings {
+            username: test_user.username.clone(),
+            password: Secret::new(test_user.password.clone()),
+        });
+        c
+    };
+    let database = TestDatabase::create(&configuration.database).await;
+    configuration.database.url = database.url.clone();
+    let db_pool = configure_database(&configuration.database).await;
+    // Tests drive deliveries themselves, so the worker is never polled.
+    let (server, port, _worker) = build(&configuration)
         .await
         .expect("Failed to build application.");
     let address = format!("http://127.0.0.1:{port}");
-    let _ = tokio::spawn(server);
+    tokio::spawn(server);
+
+    let retry_policy = configuration.email_client.retry_policy();
+    let email_client = configuration.email_client.client().unwrap();
+    let api_client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .cookie_store(true)
+        .build()
+        .unwrap();
 
     TestApp {
         address,
-        db_pool: get_connection_pool(&configuration.database).await,
+        port,
+        database,
+        db_pool,
+        email_server,
+        email_client,
+        retry_policy,
+        test_user,
+        api_client,
+    }
+}
+
+pub fn random_string(len: usize) -> String {
+    thread_rng()
+        .sample_iter(&Alphanumeric)
+        .map(char::from)
+        .take(len)
+        .collect()
+}
+
+// Every test gets an empty database of its own, removed again when the
+// test is over, so tests never see each other's rows.
+pub struct TestDatabase {
+    pub url: String,
+    #[cfg(feature = "sqlite")]
+    path: std::path::PathBuf,
+    #[cfg(feature = "postgres")]
+    name: String,
+    #[cfg(feature = "postgres")]
+    server_url: String,
+}
+
+impl TestDatabase {
+    #[cfg(feature = "sqlite")]
+    pub async fn create(_configuration: &DatabaseSettings) -> Self {
+        let name = random_string(16);
+        let path = std::env::temp_dir().join(format!("zero2prod_test_{name}.db"));
+        Self {
+            url: format!("sqlite://{}", path.display()),
+            path,
+        }
+    }
+
+    // The configured database is only used to create a fresh one next to it.
+    #[cfg(feature = "postgres")]
+    pub async fn create(configuration: &DatabaseSettings) -> Self {
+        use sqlx::{Connection, Executor, PgConnection};
+
+        let server_url = configuration.connection_string();
+        let name = format!("zero2prod_test_{}", random_string(16).to_lowercase());
+        let mut connection = PgConnection::connect(&server_url)
+            .await
+            .expect("Failed to connect to Postgres.");
+        connection
+            .execute(format!(r#"CREATE DATABASE "{name}";"#).as_str())
+            .await
+            .expect("Failed to create the database.");
+        let mut url = reqwest::Url::parse(&server_url).expect("Invalid database url.");
+        url.set_path(&name);
+        Self {
+            url: url.to_string(),
+            name,
+            server_url,
+        }
     }
 }
 
+impl Drop for TestDatabase {
+    #[cfg(feature = "sqlite")]
+    fn drop(&mut self) {
+        for suffix in ["", "-wal", "-shm"] {
+            let mut path = self.path.clone().into_os_string();
+            path.push(suffix);
+            let _ = std::fs::remove_file(path);
+        }
+    }
+
+    // `drop` cannot await, so the database is dropped from a thread with a
+    // runtime of its own. `FORCE` closes the connections the app still holds.
+    #[cfg(feature = "postgres")]
+    fn drop(&mut self) {
+        use sqlx::{Connection, Executor, PgConnection};
+
+        let server_url = self.server_url.clone();
+        let statement =
+            format!(r#"DROP DATABASE IF EXISTS "{}" WITH (FORCE);"#, self.name);
+        let _ = std::thread::spawn(move || {
+            tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .expect("Failed to build a runtime.")
+                .block_on(async move {
+                    let mut connection = PgConnection::connect(&server_url).await?;
+                    connection.execute(statement.as_str()).await
+                })
+        })
+        .join();
+    }
+}
+
+async fn configure_database(config: &DatabaseSettings) -> DbPool {
+    let db_pool = get_connection_pool(config).await;
+    MIGRATOR
+        .run(&db_pool)
+        .await
+        .expect("Failed to migrate the database.");
+    db_pool
+}
+
 pub struct TestApp {
     pub address: String,
-    pub db_pool: SqlitePool,
+    pub port: u16,
+    pub database: TestDatabase,
+    pub db_pool: DbPool,
+    pub email_server: MockServer,
+    pub email_client: EmailClient,
+    pub retry_policy: RetryPolicy,
+    pub test_user: TestUser,
+    pub api_client: reqwest::Client,
+}
+
+pub struct TestUser {
+    pub username: String,
+    pub password: String,
+}
+
+impl TestUser {
+    fn generate() -> Self {
+        Self {
+            username: random_string(12),
+            password: random_string(24),
+        }
+    }
+}
+
+// The links of a confirmation email, one per body.
+pub struct ConfirmationLinks {
+    pub html: reqwest::Url,
+    pub plain_text: reqwest::Url,
+}
+
+impl TestApp {
+    pub async fn post_subscriptions(&self, body: String) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/subscriptions", &self.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_newsletters(&self, body: &serde_json::Value) -> reqwest::Response {
+        self.post_newsletters_with_key(body, &random_string(16))
+            .await
+    }
+
+    pub async fn post_newsletters_with_key(
+        &self,
+        body: &serde_json::Value,
+        idempotency_key: &str,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/newsletters", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .header("Idempotency-Key", idempotency_key)
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_login<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/login", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_login_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/login", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn get_admin_dashboard(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/dashboard", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin_dashboard_html(&self) -> String {
+        self.get_admin_dashboard().await.text().await.unwrap()
+    }
+
+    pub async fn post_logout(&self) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/logout", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn login(&self) {
+        let response = self
+            .post_login(&serde_json::json!({
+                "username": &self.test_user.username,
+                "password": &self.test_user.password,
+            }))
+            .await;
+        assert_is_redirect_to(&response, "/admin/dashboard");
+    }
+
+    pub async fn dispatch_all_pending_emails(&self) {
+        release_scheduled_issues(&self.db_pool).await.unwrap();
+        loop {
+            if let ExecutionOutcome::EmptyQueue = try_execute_task(
+                &self.db_pool,
+                &self.email_client,
+                &self.retry_policy,
+                &self.address,
+            )
+            .await
+            .unwrap()
+            {
+                break;
+            }
+        }
+        mark_delivered_issues(&self.db_pool).await.unwrap();
+    }
+
+    // Links in the captured email point at the configured base url, which
+    // has no port; they are pointed at this app instead.
+    pub fn get_confirmation_links(
+        &self,
+        email_request: &wiremock::Request,
+    ) -> ConfirmationLinks {
+        let body: serde_json::Value =
+            serde_json::from_slice(&email_request.body).unwrap();
+        let get_link = |s: &str| {
+            let links: Vec<_> = linkify::LinkFinder::new()
+                .links(s)
+                .filter(|l| *l.kind() == linkify::LinkKind::Url)
+                .collect();
+            assert_eq!(links.len(), 1);
+            let mut confirmation_link = reqwest::Url::parse(links[0].as_str()).unwrap();
+            assert_eq!(confirmation_link.host_str().unwrap(), "127.0.0.1");
+            confirmation_link.set_port(Some(self.port)).unwrap();
+            confirmation_link
+        };
+        ConfirmationLinks {
+            html: get_link(body["HtmlBody"].as_str().unwrap()),
+            plain_text: get_link(body["TextBody"].as_str().unwrap()),
+        }
+    }
+}
+
+pub fn assert_is_redirect_to(response: &reqwest::Response, location: &str) {
+    assert_eq!(response.status().as_u16(), 303);
+    assert_eq!(response.headers().get("Location").unwrap(), location);
 }
@@ -0,0 +1,32 @@
+use super::helpers::spawn_app;
+use super::newsletters::create_confirmed_subscriber;
+use claim::assert_err;
+use sqlx::{Connection, Database};
+use std::str::FromStr;
+use zero2prod::database::{Db, DbConnectOptions};
+
+#[actix_rt::test]
+async fn every_app_starts_with_an_empty_database() {
+    let first = spawn_app().await;
+    let second = spawn_app().await;
+
+    create_confirmed_subscriber(&first).await;
+
+    let subscribers =
+        sqlx::query!(r#"SELECT COUNT(*) AS "count!: i64" FROM subscriptions"#)
+            .fetch_one(&second.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(subscribers.count, 0);
+}
+
+#[actix_rt::test]
+async fn the_database_is_removed_with_the_app() {
+    let app = spawn_app().await;
+    let url = app.database.url.clone();
+
+    drop(app);
+
+    let options = DbConnectOptions::from_str(&url).unwrap();
+    assert_err!(<Db as Database>::Connection::connect_with(&options).await);
+}
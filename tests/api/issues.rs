@@ -0,0 +1,258 @@
+use super::helpers::{spawn_app, TestApp};
+use super::newsletters::create_confirmed_subscriber;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+fn draft_body(title: &str) -> serde_json::Value {
+    serde_json::json!({
+        "title": title,
+        "content": {"markdown": "Hi {{name}}!\n\n[Unsubscribe]({{unsubscribe_link}})"}
+    })
+}
+
+async fn create_draft(app: &TestApp) -> i64 {
+    let response = app
+        .api_client
+        .post(format!("{}/admin/issues", app.address))
+        .json(&draft_body("Draft title"))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(201, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    body["newsletter_issue_id"].as_i64().unwrap()
+}
+
+async fn get_issue(app: &TestApp, issue_id: i64) -> reqwest::Response {
+    app.api_client
+        .get(format!("{}/admin/issues/{}", app.address, issue_id))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn post_issue_action(
+    app: &TestApp,
+    issue_id: i64,
+    action: &str,
+    body: &serde_json::Value,
+) -> reqwest::Response {
+    app.api_client
+        .post(format!(
+            "{}/admin/issues/{}/{}",
+            app.address, issue_id, action
+        ))
+        .json(body)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn issue_status(app: &TestApp, issue_id: i64) -> String {
+    let issue: serde_json::Value = get_issue(app, issue_id).await.json().await.unwrap();
+    issue["status"].as_str().unwrap().to_owned()
+}
+
+#[actix_rt::test]
+async fn drafts_are_not_delivered_to_subscribers() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.login().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let issue_id = create_draft(&app).await;
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(issue_status(&app, issue_id).await, "draft");
+}
+
+#[actix_rt::test]
+async fn drafts_can_be_previewed_with_sample_values() {
+    let app = spawn_app().await;
+    app.login().await;
+    let issue_id = create_draft(&app).await;
+
+    let preview = |format: &'static str| {
+        app.api_client
+            .get(format!(
+                "{}/admin/issues/{}/preview?format={}",
+                app.address, issue_id, format
+            ))
+            .send()
+    };
+    let html_response = preview("html").await.unwrap();
+    assert_eq!(
+        html_response.headers()["Content-Security-Policy"],
+        "sandbox"
+    );
+    let html = html_response.text().await.unwrap();
+    let text = preview("text").await.unwrap().text().await.unwrap();
+
+    assert!(html.contains("Hi Ursula Le Guin!"));
+    assert!(text.starts_with("Hi Ursula Le Guin!"));
+}
+
+#[actix_rt::test]
+async fn a_test_copy_only_goes_to_the_given_address() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.login().await;
+    let issue_id = create_draft(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = post_issue_action(
+        &app,
+        issue_id,
+        "test_send",
+        &serde_json::json!({"email": "editor@example.com"}),
+    )
+    .await;
+
+    assert_eq!(200, response.status().as_u16());
+    let requests = app.email_server.received_requests().await.unwrap();
+    let body: serde_json::Value =
+        serde_json::from_slice(&requests.last().unwrap().body).unwrap();
+    assert_eq!(body["To"], "editor@example.com");
+    assert_eq!(body["Subject"], "[Test] Draft title");
+    let queued =
+        sqlx::query!(r#"SELECT COUNT(*) AS "count!: i64" FROM issue_delivery_queue"#)
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(queued.count, 0);
+    assert_eq!(issue_status(&app, issue_id).await, "draft");
+}
+
+#[actix_rt::test]
+async fn published_drafts_go_through_sending_to_sent() {
+    let app = spawn_app().await;
+    let email = create_confirmed_subscriber(&app).await;
+    app.login().await;
+    let issue_id = create_draft(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response =
+        post_issue_action(&app, issue_id, "publish", &serde_json::json!({})).await;
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(issue_status(&app, issue_id).await, "sending");
+
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(issue_status(&app, issue_id).await, "sent");
+    let requests = app.email_server.received_requests().await.unwrap();
+    let body: serde_json::Value =
+        serde_json::from_slice(&requests.last().unwrap().body).unwrap();
+    assert_eq!(body["To"], email.as_str());
+
+    // Published issues can no longer be edited or published again.
+    let response =
+        post_issue_action(&app, issue_id, "publish", &serde_json::json!({})).await;
+    assert_eq!(409, response.status().as_u16());
+    let response = app
+        .api_client
+        .put(format!("{}/admin/issues/{}", app.address, issue_id))
+        .json(&draft_body("New title"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(409, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn drafts_cannot_be_published_with_a_send_at_in_the_past() {
+    let app = spawn_app().await;
+    app.login().await;
+    let issue_id = create_draft(&app).await;
+
+    let body = serde_json::json!({
+        "send_at": chrono::Utc::now() - chrono::Duration::minutes(1),
+    });
+    let response = post_issue_action(&app, issue_id, "publish", &body).await;
+
+    assert_eq!(400, response.status().as_u16());
+    assert_eq!(issue_status(&app, issue_id).await, "draft");
+}
+
+#[actix_rt::test]
+async fn drafts_can_be_updated_listed_and_deleted() {
+    let app = spawn_app().await;
+    app.login().await;
+    let issue_id = create_draft(&app).await;
+
+    let response = app
+        .api_client
+        .put(format!("{}/admin/issues/{}", app.address, issue_id))
+        .json(&draft_body("New title"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, response.status().as_u16());
+    let issues: serde_json::Value = app
+        .api_client
+        .get(format!("{}/admin/issues", app.address))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(issues[0]["newsletter_issue_id"], issue_id);
+    assert_eq!(issues[0]["title"], "New title");
+
+    let response = app
+        .api_client
+        .delete(format!("{}/admin/issues/{}", app.address, issue_id))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(204, response.status().as_u16());
+    assert_eq!(404, get_issue(&app, issue_id).await.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn drafts_with_unknown_template_variables_are_rejected() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    let response = app
+        .api_client
+        .post(format!("{}/admin/issues", app.address))
+        .json(&serde_json::json!({
+            "title": "Draft title",
+            "content": {"markdown": "Hi {{nmae}}!"}
+        }))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn you_must_be_logged_in_to_manage_issues() {
+    let app = spawn_app().await;
+
+    let response = app
+        .api_client
+        .post(format!("{}/admin/issues", app.address))
+        .json(&draft_body("Draft title"))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(303, response.status().as_u16());
+}
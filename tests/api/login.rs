@@ -0,0 +1,58 @@
+use super::helpers::{assert_is_redirect_to, spawn_app, spawn_app_with_session_store};
+use zero2prod::session_store::SessionStoreKind;
+
+#[actix_rt::test]
+async fn an_error_flash_message_is_set_on_failure() {
+    let app = spawn_app().await;
+    let login_body = serde_json::json!({
+        "username": "random-username",
+        "password": "random-password"
+    });
+
+    let response = app.post_login(&login_body).await;
+    assert_is_redirect_to(&response, "/login");
+
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("<p><i>Authentication failed</i></p>"));
+
+    let html_page = app.get_login_html().await;
+    assert!(!html_page.contains("Authentication failed"));
+}
+
+#[actix_rt::test]
+async fn redirect_to_admin_dashboard_after_login_success() {
+    let app = spawn_app().await;
+    let login_body = serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    });
+
+    let response = app.post_login(&login_body).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
+}
+
+#[actix_rt::test]
+async fn sessions_work_with_the_in_memory_store() {
+    let app = spawn_app_with_session_store(SessionStoreKind::Memory).await;
+
+    app.login().await;
+
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
+}
+
+#[actix_rt::test]
+async fn sessions_are_persisted_in_the_database_store() {
+    let app = spawn_app_with_session_store(SessionStoreKind::Database).await;
+
+    app.login().await;
+
+    let sessions = sqlx::query!(r#"SELECT COUNT(*) AS "count!: i64" FROM sessions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(sessions.count, 1);
+}
@@ -1,3 +1,14 @@
+mod admin_dashboard;
+mod authentication;
+mod dead_letters;
 mod health_check;
 mod helpers;
+mod isolation;
+mod issues;
+mod login;
+mod migrations;
+mod newsletters;
+mod scheduled_issues;
 mod subscriptions;
+mod subscriptions_confirm;
+mod subscriptions_unsubscribe;
@@ -0,0 +1,76 @@
+use super::helpers::{spawn_app, TestDatabase};
+use zero2prod::configuration::{get_configuration, Settings};
+use zero2prod::database::MIGRATOR;
+use zero2prod::startup::{build, get_connection_pool};
+
+async fn configuration_with_empty_database() -> (Settings, TestDatabase) {
+    let mut configuration = get_configuration().unwrap();
+    let database = TestDatabase::create(&configuration.database).await;
+    configuration.database.url = database.url.clone();
+    configuration.database.migrate_on_startup = true;
+    (configuration, database)
+}
+
+async fn applied_migrations(configuration: &Settings) -> i64 {
+    let pool = get_connection_pool(&configuration.database).await;
+    sqlx::query!(r#"SELECT COUNT(*) AS "count!: i64" FROM _sqlx_migrations"#)
+        .fetch_one(&pool)
+        .await
+        .unwrap()
+        .count
+}
+
+#[actix_rt::test]
+async fn migrations_are_applied_on_startup() {
+    let (configuration, _database) = configuration_with_empty_database().await;
+
+    let (_server, _, _) = build(&configuration)
+        .await
+        .expect("Failed to build application.");
+
+    assert_eq!(
+        applied_migrations(&configuration).await,
+        MIGRATOR.iter().count() as i64
+    );
+}
+
+#[actix_rt::test]
+async fn replicas_starting_together_apply_each_migration_once() {
+    let (configuration, _database) = configuration_with_empty_database().await;
+
+    let (first, second) = tokio::join!(build(&configuration), build(&configuration));
+
+    assert!(first.is_ok());
+    assert!(second.is_ok());
+    assert_eq!(
+        applied_migrations(&configuration).await,
+        MIGRATOR.iter().count() as i64
+    );
+}
+
+#[actix_rt::test]
+async fn startup_fails_if_the_database_has_unknown_migrations() {
+    let app = spawn_app().await;
+    // Pretend a newer release already migrated this database.
+    sqlx::query!(
+        r#"
+            INSERT INTO _sqlx_migrations
+            (version, description, success, checksum, execution_time)
+            SELECT 99990101000000, 'from a newer release', success, checksum, execution_time
+            FROM _sqlx_migrations
+            WHERE version = (SELECT MAX(version) FROM _sqlx_migrations)
+        "#
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    let mut configuration = get_configuration().unwrap();
+    configuration.database.url = app.database.url.clone();
+    configuration.database.migrate_on_startup = true;
+
+    let Err(error) = build(&configuration).await else {
+        panic!("The application started on a database with unknown migrations.");
+    };
+
+    assert!(error.to_string().contains("99990101000000"));
+}
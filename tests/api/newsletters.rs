@@ -0,0 +1,650 @@
+use super::helpers::{random_string, spawn_app, TestApp};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::issue_delivery_worker::{try_execute_task, ExecutionOutcome};
+
+// Every call subscribes a new address, so one app can hold several subscribers.
+pub async fn create_unconfirmed_subscriber(app: &TestApp) -> (String, reqwest::Url) {
+    let local_part = random_string(12).to_lowercase();
+    let email = format!("{local_part}@gmail.com");
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .named("Create unconfirmed subscriber")
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_subscriptions(format!("name=le%20guin&email={local_part}%40gmail.com"))
+        .await
+        .error_for_status()
+        .unwrap();
+    let email_request = &app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    (email, app.get_confirmation_links(email_request).html)
+}
+
+pub async fn create_confirmed_subscriber(app: &TestApp) -> String {
+    let (email, confirmation_link) = create_unconfirmed_subscriber(app).await;
+    reqwest::get(confirmation_link)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    email
+}
+
+async fn sent_emails(app: &TestApp) -> usize {
+    app.email_server.received_requests().await.unwrap().len()
+}
+
+async fn newsletter_recipients(app: &TestApp, already_sent: usize) -> Vec<String> {
+    app.email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .skip(already_sent)
+        .map(|r| {
+            let body: serde_json::Value = serde_json::from_slice(&r.body).unwrap();
+            body["To"].as_str().unwrap().to_owned()
+        })
+        .collect()
+}
+
+pub fn newsletter_request_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    })
+}
+
+#[actix_rt::test]
+async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
+    let app = spawn_app().await;
+    let (email, _) = create_unconfirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let already_sent = sent_emails(&app).await;
+
+    let response = app.post_newsletters(&newsletter_request_body()).await;
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(200, response.status().as_u16());
+    assert!(!newsletter_recipients(&app, already_sent)
+        .await
+        .contains(&email));
+}
+
+#[actix_rt::test]
+async fn newsletters_are_delivered_to_confirmed_subscribers() {
+    let app = spawn_app().await;
+    let email = create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let already_sent = sent_emails(&app).await;
+
+    let response = app.post_newsletters(&newsletter_request_body()).await;
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(200, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["deliveries_queued"], 1);
+    assert!(newsletter_recipients(&app, already_sent)
+        .await
+        .contains(&email));
+}
+
+#[actix_rt::test]
+async fn newsletters_are_delivered_to_every_confirmed_subscriber() {
+    let app = spawn_app().await;
+    let first_email = create_confirmed_subscriber(&app).await;
+    let second_email = create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    let already_sent = sent_emails(&app).await;
+
+    let response = app.post_newsletters(&newsletter_request_body()).await;
+    app.dispatch_all_pending_emails().await;
+
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["deliveries_queued"], 2);
+    let recipients = newsletter_recipients(&app, already_sent).await;
+    assert!(recipients.contains(&first_email));
+    assert!(recipients.contains(&second_email));
+}
+
+#[actix_rt::test]
+async fn confirmed_subscribers_with_an_invalid_stored_email_are_skipped() {
+    let app = spawn_app().await;
+    let invalid_email = "definitely-not-an-email".to_string();
+    let subscribed_at = chrono::Utc::now();
+    sqlx::query!(
+        r#"
+            INSERT INTO subscriptions (email, name, subscribed_at, status)
+            VALUES ($1, 'le guin', $2, 'confirmed')
+        "#,
+        invalid_email,
+        subscribed_at,
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let already_sent = sent_emails(&app).await;
+
+    let response = app.post_newsletters(&newsletter_request_body()).await;
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(200, response.status().as_u16());
+    assert!(!newsletter_recipients(&app, already_sent)
+        .await
+        .contains(&invalid_email));
+}
+
+#[actix_rt::test]
+async fn newsletters_are_personalised_for_each_subscriber() {
+    let app = spawn_app().await;
+    let email = create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "html": "<p>Hi {{name}}!</p><p><a href=\"{{unsubscribe_link}}\">Leave</a></p>",
+        }
+    });
+
+    app.post_newsletters(&body)
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let requests = app.email_server.received_requests().await.unwrap();
+    let delivered: serde_json::Value = requests
+        .iter()
+        .map(|r| serde_json::from_slice(&r.body).unwrap())
+        .find(|b: &serde_json::Value| b["To"] == email && b["Subject"] != "Welcome!")
+        .unwrap();
+    let html = delivered["HtmlBody"].as_str().unwrap();
+    let text = delivered["TextBody"].as_str().unwrap();
+    assert!(html.starts_with("<p>Hi le guin!</p>"));
+    assert!(html.contains("/subscriptions/unsubscribe?unsubscribe_token="));
+    assert!(text.starts_with("Hi le guin!\n\nLeave ("));
+}
+
+#[actix_rt::test]
+async fn markdown_newsletters_are_rendered_to_html_and_text() {
+    let app = spawn_app().await;
+    let email = create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {"markdown": "Hi **{{name}}**!\n\n<script>alert(1)</script>"}
+    });
+
+    app.post_newsletters(&body)
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let requests = app.email_server.received_requests().await.unwrap();
+    let delivered: serde_json::Value = requests
+        .iter()
+        .map(|r| serde_json::from_slice(&r.body).unwrap())
+        .find(|b: &serde_json::Value| b["To"] == email && b["Subject"] != "Welcome!")
+        .unwrap();
+    let html = delivered["HtmlBody"].as_str().unwrap();
+    assert!(html.contains("Hi <strong>le guin</strong>!"));
+    assert!(!html.contains("script"));
+    assert_eq!(delivered["TextBody"], "Hi le guin!");
+}
+
+#[actix_rt::test]
+async fn braces_in_markdown_code_are_delivered_as_written() {
+    let app = spawn_app().await;
+    let email = create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {"markdown": "Hi {{name}}, try `{{ user }}`."}
+    });
+
+    app.post_newsletters(&body)
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let requests = app.email_server.received_requests().await.unwrap();
+    let delivered: serde_json::Value = requests
+        .iter()
+        .map(|r| serde_json::from_slice(&r.body).unwrap())
+        .find(|b: &serde_json::Value| b["To"] == email && b["Subject"] != "Welcome!")
+        .unwrap();
+    assert_eq!(delivered["TextBody"], "Hi le guin, try `{{ user }}`.");
+}
+
+#[actix_rt::test]
+async fn newsletters_returns_400_for_invalid_data() {
+    let app = spawn_app().await;
+    let test_cases = vec![
+        (
+            serde_json::json!({
+                "content": {
+                    "text": "Newsletter body as plain text",
+                    "html": "<p>Newsletter body as HTML</p>",
+                }
+            }),
+            "missing title",
+        ),
+        (
+            serde_json::json!({"title": "Newsletter!"}),
+            "missing content",
+        ),
+        (
+            serde_json::json!({
+                "title": "Newsletter!",
+                "content": {"text": "Hi {{nmae}}", "html": "<p>Hi</p>"}
+            }),
+            "unknown template variable",
+        ),
+    ];
+    for (invalid_body, error_message) in test_cases {
+        let response = app.post_newsletters(&invalid_body).await;
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request when the payload was {}.",
+            error_message
+        );
+    }
+}
+
+#[actix_rt::test]
+async fn failed_deliveries_are_rescheduled_with_a_backoff() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_newsletters(&newsletter_request_body())
+        .await
+        .error_for_status()
+        .unwrap();
+    let outcome = try_execute_task(
+        &app.db_pool,
+        &app.email_client,
+        &app.retry_policy,
+        &app.address,
+    )
+    .await;
+
+    assert!(outcome.is_err());
+    let queued = sqlx::query!(
+        r#"SELECT n_retries, execute_after AS "execute_after: chrono::DateTime<chrono::Utc>" FROM issue_delivery_queue"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(queued.n_retries, 1);
+    assert!(queued.execute_after.is_some());
+    let outcome = try_execute_task(
+        &app.db_pool,
+        &app.email_client,
+        &app.retry_policy,
+        &app.address,
+    )
+    .await;
+    assert!(matches!(outcome, Ok(ExecutionOutcome::EmptyQueue)));
+}
+
+#[actix_rt::test]
+async fn rate_limited_deliveries_wait_for_the_retry_after_delay() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "7200"))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_newsletters(&newsletter_request_body())
+        .await
+        .error_for_status()
+        .unwrap();
+    let outcome = try_execute_task(
+        &app.db_pool,
+        &app.email_client,
+        &app.retry_policy,
+        &app.address,
+    )
+    .await;
+
+    assert!(outcome.is_err());
+    let queued = sqlx::query!(
+        r#"
+            SELECT execute_after AS "execute_after!: chrono::DateTime<chrono::Utc>"
+            FROM issue_delivery_queue
+        "#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert!(queued.execute_after > chrono::Utc::now() + chrono::Duration::minutes(110));
+}
+
+#[actix_rt::test]
+async fn requests_missing_authorization_are_rejected() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters", app.address))
+        .json(&newsletter_request_body())
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(
+        r#"Basic realm="publish""#,
+        response.headers()["WWW-Authenticate"]
+    );
+}
+
+#[actix_rt::test]
+async fn unauthenticated_requests_with_an_invalid_body_are_rejected_with_a_401() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters", app.address))
+        .json(&serde_json::json!({"title": "Newsletter!"}))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(
+        r#"Basic realm="publish""#,
+        response.headers()["WWW-Authenticate"]
+    );
+}
+
+#[actix_rt::test]
+async fn non_existing_user_is_rejected() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters", app.address))
+        .basic_auth("unknown-user", Some("password"))
+        .json(&newsletter_request_body())
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(
+        r#"Basic realm="publish""#,
+        response.headers()["WWW-Authenticate"]
+    );
+}
+
+#[actix_rt::test]
+async fn invalid_password_is_rejected() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters", app.address))
+        .basic_auth(&app.test_user.username, Some("definitely-not-the-password"))
+        .json(&newsletter_request_body())
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(
+        r#"Basic realm="publish""#,
+        response.headers()["WWW-Authenticate"]
+    );
+}
+
+#[actix_rt::test]
+async fn logged_in_admins_can_publish_without_basic_credentials() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    let response = app
+        .api_client
+        .post(format!("{}/newsletters", app.address))
+        .header("Idempotency-Key", random_string(16))
+        .json(&newsletter_request_body())
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn requests_missing_an_idempotency_key_are_rejected() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters", app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .json(&newsletter_request_body())
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn newsletter_creation_is_idempotent() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let idempotency_key = random_string(16);
+
+    let response = app
+        .post_newsletters_with_key(&newsletter_request_body(), &idempotency_key)
+        .await;
+    assert_eq!(200, response.status().as_u16());
+    let first_body = response.text().await.unwrap();
+
+    let response = app
+        .post_newsletters_with_key(&newsletter_request_body(), &idempotency_key)
+        .await;
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        "application/json",
+        response.headers()["Content-Type"].to_str().unwrap()
+    );
+    assert_eq!(first_body, response.text().await.unwrap());
+
+    app.dispatch_all_pending_emails().await;
+}
+
+#[actix_rt::test]
+async fn concurrent_publish_requests_are_handled_gracefully() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let idempotency_key = random_string(16);
+    let body = newsletter_request_body();
+
+    let response1 = app.post_newsletters_with_key(&body, &idempotency_key);
+    let response2 = app.post_newsletters_with_key(&body, &idempotency_key);
+    let (response1, response2) = tokio::join!(response1, response2);
+
+    assert_eq!(response1.status(), response2.status());
+    assert_eq!(
+        response1.text().await.unwrap(),
+        response2.text().await.unwrap()
+    );
+    app.dispatch_all_pending_emails().await;
+}
+
+#[actix_rt::test]
+async fn requests_wait_for_an_in_flight_request_with_the_same_key() {
+    let app = spawn_app().await;
+    let idempotency_key = random_string(16);
+    let body = newsletter_request_body();
+    let user_id = test_user_id(&app).await;
+    // Stands in for a first request that has claimed the key but not saved
+    // its response yet.
+    insert_idempotency_placeholder(&app, user_id, &idempotency_key).await;
+
+    let response = app.post_newsletters_with_key(&body, &idempotency_key);
+    let first_request_completes = async {
+        tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+        let saved_body = br#"{"in_flight":true}"#.as_slice();
+        sqlx::query!(
+            r#"
+                UPDATE idempotency
+                SET response_status_code = 200, response_headers = '[]',
+                    response_body = $1
+                WHERE user_id = $2 AND idempotency_key = $3
+            "#,
+            saved_body,
+            user_id,
+            idempotency_key
+        )
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    };
+    let (response, _) = tokio::join!(response, first_request_completes);
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(r#"{"in_flight":true}"#, response.text().await.unwrap());
+    let n_issues =
+        sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!: i64" FROM newsletter_issues"#)
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(0, n_issues);
+}
+
+#[actix_rt::test]
+async fn requests_give_up_on_an_in_flight_request_that_never_finishes() {
+    let app = spawn_app().await;
+    let idempotency_key = random_string(16);
+    let user_id = test_user_id(&app).await;
+    insert_idempotency_placeholder(&app, user_id, &idempotency_key).await;
+
+    let response = app
+        .post_newsletters_with_key(&newsletter_request_body(), &idempotency_key)
+        .await;
+
+    assert_eq!(409, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn many_concurrent_requests_with_the_same_key_publish_a_single_issue() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let idempotency_key = random_string(16);
+    let body = newsletter_request_body();
+
+    let responses = tokio::join!(
+        app.post_newsletters_with_key(&body, &idempotency_key),
+        app.post_newsletters_with_key(&body, &idempotency_key),
+        app.post_newsletters_with_key(&body, &idempotency_key),
+        app.post_newsletters_with_key(&body, &idempotency_key),
+    );
+
+    let mut bodies = Vec::new();
+    for response in <[_; 4]>::from(responses) {
+        assert_eq!(200, response.status().as_u16());
+        bodies.push(response.text().await.unwrap());
+    }
+    assert!(bodies.windows(2).all(|pair| pair[0] == pair[1]));
+    let n_issues =
+        sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!: i64" FROM newsletter_issues"#)
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(1, n_issues);
+    app.dispatch_all_pending_emails().await;
+}
+
+async fn test_user_id(app: &TestApp) -> i64 {
+    sqlx::query_scalar!(
+        r#"SELECT id FROM users WHERE username = $1"#,
+        app.test_user.username
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+}
+
+async fn insert_idempotency_placeholder(app: &TestApp, user_id: i64, key: &str) {
+    let now = chrono::Utc::now();
+    sqlx::query!(
+        r#"
+            INSERT INTO idempotency (user_id, idempotency_key, created_at)
+            VALUES ($1, $2, $3)
+        "#,
+        user_id,
+        key,
+        now
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
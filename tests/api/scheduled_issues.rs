@@ -0,0 +1,194 @@
+use super::helpers::{spawn_app, TestApp};
+use super::newsletters::create_confirmed_subscriber;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+fn scheduled_request_body(send_at: chrono::DateTime<chrono::Utc>) -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        },
+        "send_at": send_at,
+    })
+}
+
+async fn schedule_issue(app: &TestApp, send_at: chrono::DateTime<chrono::Utc>) -> i64 {
+    let response = app.post_newsletters(&scheduled_request_body(send_at)).await;
+    assert_eq!(200, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["deliveries_queued"], 0);
+    body["newsletter_issue_id"].as_i64().unwrap()
+}
+
+async fn post_issue_action(
+    app: &TestApp,
+    issue_id: i64,
+    action: &str,
+    body: &serde_json::Value,
+) -> reqwest::Response {
+    app.api_client
+        .post(format!(
+            "{}/admin/issues/{}/{}",
+            app.address, issue_id, action
+        ))
+        .json(body)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn scheduled_issues(app: &TestApp) -> Vec<serde_json::Value> {
+    app.api_client
+        .get(format!("{}/admin/scheduled_issues", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .json::<serde_json::Value>()
+        .await
+        .unwrap()
+        .as_array()
+        .unwrap()
+        .clone()
+}
+
+#[actix_rt::test]
+async fn scheduled_issues_are_not_delivered_before_send_at() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    schedule_issue(&app, chrono::Utc::now() + chrono::Duration::hours(1)).await;
+    app.dispatch_all_pending_emails().await;
+}
+
+#[actix_rt::test]
+async fn scheduled_issues_are_delivered_once_send_at_has_passed() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.login().await;
+    let issue_id =
+        schedule_issue(&app, chrono::Utc::now() + chrono::Duration::hours(1)).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // The API only takes times in the future, so time passes in the database.
+    let send_at = chrono::Utc::now() - chrono::Duration::seconds(1);
+    sqlx::query!(
+        "UPDATE newsletter_issues SET send_at = $1 WHERE id = $2",
+        send_at,
+        issue_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    assert!(scheduled_issues(&app).await.is_empty());
+}
+
+#[actix_rt::test]
+async fn publishing_with_a_send_at_in_the_past_is_rejected() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let send_at = chrono::Utc::now() - chrono::Duration::minutes(1);
+    let response = app.post_newsletters(&scheduled_request_body(send_at)).await;
+
+    assert_eq!(400, response.status().as_u16());
+    app.dispatch_all_pending_emails().await;
+}
+
+#[actix_rt::test]
+async fn scheduled_issues_can_be_rescheduled() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.login().await;
+    let issue_id =
+        schedule_issue(&app, chrono::Utc::now() + chrono::Duration::hours(1)).await;
+
+    // Whole seconds, so the round trip through the database is exact.
+    let in_two_hours = chrono::Utc::now().timestamp() + 2 * 60 * 60;
+    let send_at = chrono::DateTime::from_timestamp(in_two_hours, 0).unwrap();
+    let body = serde_json::json!({"send_at": send_at});
+    let response = post_issue_action(&app, issue_id, "reschedule", &body).await;
+
+    assert_eq!(200, response.status().as_u16());
+    let issues = scheduled_issues(&app).await;
+    let rescheduled: chrono::DateTime<chrono::Utc> =
+        serde_json::from_value(issues[0]["send_at"].clone()).unwrap();
+    assert_eq!(rescheduled, send_at);
+}
+
+#[actix_rt::test]
+async fn rescheduling_into_the_past_is_rejected() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.login().await;
+    let issue_id =
+        schedule_issue(&app, chrono::Utc::now() + chrono::Duration::hours(1)).await;
+
+    let body = serde_json::json!({
+        "send_at": chrono::Utc::now() - chrono::Duration::seconds(1),
+    });
+    let response = post_issue_action(&app, issue_id, "reschedule", &body).await;
+
+    assert_eq!(400, response.status().as_u16());
+    assert_eq!(scheduled_issues(&app).await.len(), 1);
+}
+
+#[actix_rt::test]
+async fn admins_can_list_and_cancel_scheduled_issues() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.login().await;
+    let issue_id =
+        schedule_issue(&app, chrono::Utc::now() + chrono::Duration::hours(1)).await;
+
+    let issues = scheduled_issues(&app).await;
+    assert_eq!(issues.len(), 1);
+    assert_eq!(issues[0]["newsletter_issue_id"], issue_id);
+
+    let body = serde_json::json!({});
+    let response = post_issue_action(&app, issue_id, "cancel", &body).await;
+    assert_eq!(200, response.status().as_u16());
+    assert!(scheduled_issues(&app).await.is_empty());
+
+    // An issue can only be cancelled once.
+    let response = post_issue_action(&app, issue_id, "cancel", &body).await;
+    assert_eq!(409, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn cancelling_an_unknown_issue_returns_a_404() {
+    let app = spawn_app().await;
+    app.login().await;
+
+    let response = post_issue_action(&app, 4242, "cancel", &serde_json::json!({})).await;
+
+    assert_eq!(404, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn you_must_be_logged_in_to_manage_scheduled_issues() {
+    let app = spawn_app().await;
+
+    let response = post_issue_action(&app, 1, "cancel", &serde_json::json!({})).await;
+
+    assert_eq!(303, response.status().as_u16());
+}
@@ -1,26 +1,28 @@
-use super::helpers::{spawn_app, unix_timestamp};
+use super::helpers::{spawn_app, TestApp};
+use super::newsletters::{create_confirmed_subscriber, create_unconfirmed_subscriber};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
 
 #[actix_rt::test]
 async fn subscribe_returns_a_200_for_valid_form_data() {
     let app = spawn_app().await;
-    let client = reqwest::Client::new();
-    let now = unix_timestamp();
-    let name = format!("John Woo_{now}");
-    let email = format!("{now}_ursula_le_guin%40ya.ru");
-    let email_with_dog = format!("{now}_ursula_le_guin@ya.ru");
-    let response = client
-        .post(&format!("{}/subscriptions", app.address))
-        .header("Content-Type", "application/x-www-form-urlencoded")
-        .body(format!("name={}&email={}", name, email))
-        .send()
-        .await
-        .expect("Failed to execute request.");
+    let name = "John Woo";
+    let email = "ursula_le_guin%40ya.ru";
+    let email_with_dog = "ursula_le_guin@ya.ru";
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let response = app
+        .post_subscriptions(format!("name={}&email={}", name, email))
+        .await;
     assert_eq!(200, response.status().as_u16());
 
     let query = sqlx::query!(
         r#"
-            SELECT email, name FROM subscriptions
-            where email = ? and name = ?
+            SELECT email, name, status FROM subscriptions
+            where email = $1 and name = $2
         "#,
         email_with_dog,
         name
@@ -32,25 +34,54 @@ async fn subscribe_returns_a_200_for_valid_form_data() {
         .expect("Failed to fetch saved subscription.");
     assert_eq!(saved.email, email_with_dog);
     assert_eq!(saved.name, name);
+    assert_eq!(saved.status.as_deref(), Some("pending_confirmation"));
+}
+
+#[actix_rt::test]
+async fn subscribe_sends_a_confirmation_email_with_a_link() {
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.into()).await;
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    assert_eq!(confirmation_links.html.path(), "/subscriptions/confirm");
+    assert_eq!(confirmation_links.html, confirmation_links.plain_text);
+}
+
+#[actix_rt::test]
+async fn subscribe_fails_if_the_confirmation_email_cannot_be_sent() {
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_subscriptions(body.into()).await;
+
+    assert_eq!(500, response.status().as_u16());
 }
 
 #[actix_rt::test]
 async fn subscribe_returns_a_400_when_data_is_missing() {
     let app = spawn_app().await;
-    let client = reqwest::Client::new();
     let test_cases = vec![
         ("name=le%20guin", "missing the email"),
         ("email=ursula_le_guin%40gmail.com", "missing the name"),
         ("", "missing both name and email"),
     ];
     for (invalid_body, error_message) in test_cases {
-        let response = client
-            .post(&format!("{}/subscriptions", app.address))
-            .header("Content-Type", "application/x-www-form-urlencoded")
-            .body(invalid_body)
-            .send()
-            .await
-            .expect("Failed to execute request.");
+        let response = app.post_subscriptions(invalid_body.into()).await;
         assert_eq!(
             400,
             response.status().as_u16(),
@@ -63,20 +94,13 @@ async fn subscribe_returns_a_400_when_data_is_missing() {
 #[actix_rt::test]
 async fn subscribe_returns_a_400_when_fields_are_present_but_invalid() {
     let app = spawn_app().await;
-    let client = reqwest::Client::new();
     let test_cases = vec![
         ("name=&email=ursula_le_guin%40gmail.com", "empty name"),
         ("name=Ursula&email=", "empty email"),
         ("name=Ursula&email=definitely-not-an-email", "invalid email"),
     ];
     for (body, description) in test_cases {
-        let response = client
-            .post(&format!("{}/subscriptions", &app.address))
-            .header("Content-Type", "application/x-www-form-urlencoded")
-            .body(body)
-            .send()
-            .await
-            .expect("Failed to execute request.");
+        let response = app.post_subscriptions(body.into()).await;
         assert_eq!(
             400,
             response.status().as_u16(),
@@ -85,3 +109,105 @@ async fn subscribe_returns_a_400_when_fields_are_present_but_invalid() {
         );
     }
 }
+
+async fn subscriber_status(app: &TestApp, email: &str) -> Option<String> {
+    sqlx::query!(
+        r#"SELECT status FROM subscriptions WHERE email = $1"#,
+        email
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("Failed to fetch saved subscription.")
+    .status
+}
+
+#[actix_rt::test]
+async fn subscribing_twice_while_pending_resends_the_confirmation_email() {
+    let app = spawn_app().await;
+    let (email, _) = create_unconfirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_subscriptions(format!("name=le%20guin&email={email}"))
+        .await;
+
+    assert_eq!(200, response.status().as_u16());
+    let email_request = app.email_server.received_requests().await.unwrap().pop();
+    let confirmation_links = app.get_confirmation_links(&email_request.unwrap());
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    assert_eq!(
+        subscriber_status(&app, &email).await.as_deref(),
+        Some("confirmed")
+    );
+}
+
+#[actix_rt::test]
+async fn subscribing_again_when_confirmed_succeeds_without_sending_an_email() {
+    let app = spawn_app().await;
+    let email = create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_subscriptions(format!("name=le%20guin&email={email}"))
+        .await;
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        subscriber_status(&app, &email).await.as_deref(),
+        Some("confirmed")
+    );
+}
+
+#[actix_rt::test]
+async fn subscribing_again_after_unsubscribing_reactivates_through_confirmation() {
+    let app = spawn_app().await;
+    let email = create_confirmed_subscriber(&app).await;
+    sqlx::query!(
+        r#"UPDATE subscriptions SET status = 'unsubscribed' WHERE email = $1"#,
+        email
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_subscriptions(format!("name=le%20guin&email={email}"))
+        .await;
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        subscriber_status(&app, &email).await.as_deref(),
+        Some("unsubscribed")
+    );
+    let email_request = app.email_server.received_requests().await.unwrap().pop();
+    let confirmation_links = app.get_confirmation_links(&email_request.unwrap());
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    assert_eq!(
+        subscriber_status(&app, &email).await.as_deref(),
+        Some("confirmed")
+    );
+}
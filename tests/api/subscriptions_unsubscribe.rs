@@ -0,0 +1,212 @@
+use super::helpers::{spawn_app, TestApp};
+use super::newsletters::{
+    create_confirmed_subscriber, create_unconfirmed_subscriber, newsletter_request_body,
+};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+// Publishes an issue to a single confirmed subscriber and returns the
+// `Headers` the newsletter email was sent with.
+async fn newsletter_headers(app: &TestApp) -> Vec<serde_json::Value> {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_newsletters(&newsletter_request_body())
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    body["Headers"].as_array().unwrap().to_owned()
+}
+
+fn header_value<'a>(headers: &'a [serde_json::Value], name: &str) -> &'a str {
+    headers
+        .iter()
+        .find(|h| h["Name"] == name)
+        .and_then(|h| h["Value"].as_str())
+        .unwrap()
+}
+
+fn unsubscribe_link(headers: &[serde_json::Value]) -> String {
+    header_value(headers, "List-Unsubscribe")
+        .trim_start_matches('<')
+        .trim_end_matches('>')
+        .to_owned()
+}
+
+// Submits the form the unsubscribe link leads to.
+async fn unsubscribe(link: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(link)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn subscriber_status(app: &TestApp, email: &str) -> Option<String> {
+    sqlx::query!(
+        r#"SELECT status FROM subscriptions WHERE email = $1"#,
+        email
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("Failed to fetch saved subscription.")
+    .status
+}
+
+#[actix_rt::test]
+async fn newsletter_emails_carry_one_click_unsubscribe_headers() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    let headers = newsletter_headers(&app).await;
+
+    assert!(unsubscribe_link(&headers)
+        .starts_with(&format!("{}/subscriptions/unsubscribe?", app.address)));
+    assert_eq!(
+        "List-Unsubscribe=One-Click",
+        header_value(&headers, "List-Unsubscribe-Post")
+    );
+}
+
+#[actix_rt::test]
+async fn following_the_unsubscribe_link_only_shows_a_confirmation_form() {
+    let app = spawn_app().await;
+    let email = create_confirmed_subscriber(&app).await;
+    let link = unsubscribe_link(&newsletter_headers(&app).await);
+
+    let response = reqwest::get(&link).await.unwrap();
+
+    assert_eq!(200, response.status().as_u16());
+    let page = response.text().await.unwrap();
+    let action = link.trim_start_matches(&app.address);
+    assert!(page.contains(&format!(r#"<form action="{}" method="post">"#, action)));
+    assert_eq!(
+        subscriber_status(&app, &email).await.as_deref(),
+        Some("confirmed")
+    );
+}
+
+#[actix_rt::test]
+async fn submitting_the_unsubscribe_form_unsubscribes_the_subscriber() {
+    let app = spawn_app().await;
+    let email = create_confirmed_subscriber(&app).await;
+    let link = unsubscribe_link(&newsletter_headers(&app).await);
+
+    let response = unsubscribe(&link).await;
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        subscriber_status(&app, &email).await.as_deref(),
+        Some("unsubscribed")
+    );
+}
+
+#[actix_rt::test]
+async fn one_click_post_unsubscribes_the_subscriber() {
+    let app = spawn_app().await;
+    let email = create_confirmed_subscriber(&app).await;
+    let link = unsubscribe_link(&newsletter_headers(&app).await);
+
+    let response = reqwest::Client::new()
+        .post(&link)
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("List-Unsubscribe=One-Click")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        subscriber_status(&app, &email).await.as_deref(),
+        Some("unsubscribed")
+    );
+}
+
+#[actix_rt::test]
+async fn old_confirmation_links_do_not_resubscribe_after_unsubscribing() {
+    let app = spawn_app().await;
+    let (email, confirmation_link) = create_unconfirmed_subscriber(&app).await;
+    reqwest::get(confirmation_link.clone())
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let link = unsubscribe_link(&newsletter_headers(&app).await);
+    unsubscribe(&link).await.error_for_status().unwrap();
+
+    let response = reqwest::get(confirmation_link).await.unwrap();
+
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(
+        subscriber_status(&app, &email).await.as_deref(),
+        Some("unsubscribed")
+    );
+}
+
+#[actix_rt::test]
+async fn unsubscribing_with_an_unknown_token_is_rejected_with_a_401() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(format!(
+        "{}/subscriptions/unsubscribe?unsubscribe_token=unknown",
+        app.address
+    ))
+    .await
+    .unwrap();
+
+    assert_eq!(401, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn unsubscribed_subscribers_do_not_receive_newsletters() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let link = unsubscribe_link(&newsletter_headers(&app).await);
+    unsubscribe(&link).await.error_for_status().unwrap();
+    let already_sent = app.email_server.received_requests().await.unwrap().len();
+
+    let response = app.post_newsletters(&newsletter_request_body()).await;
+    app.dispatch_all_pending_emails().await;
+
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(0, body["deliveries_queued"].as_u64().unwrap());
+    assert_eq!(
+        already_sent,
+        app.email_server.received_requests().await.unwrap().len()
+    );
+}
+
+#[actix_rt::test]
+async fn queued_deliveries_are_dropped_for_subscribers_who_unsubscribe() {
+    let app = spawn_app().await;
+    let email = create_confirmed_subscriber(&app).await;
+    let link = unsubscribe_link(&newsletter_headers(&app).await);
+    app.post_newsletters(&newsletter_request_body())
+        .await
+        .error_for_status()
+        .unwrap();
+    let already_sent = app.email_server.received_requests().await.unwrap().len();
+
+    unsubscribe(&link).await.error_for_status().unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(
+        subscriber_status(&app, &email).await.as_deref(),
+        Some("unsubscribed")
+    );
+    assert_eq!(
+        already_sent,
+        app.email_server.received_requests().await.unwrap().len()
+    );
+}